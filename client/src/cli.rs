@@ -0,0 +1,269 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::time::Duration;
+use tftp_libs::{Mode, DEFAULT_RETRIES, DEFAULT_TIMEOUT};
+
+pub const USAGE: &str = "Usage:
+  client [HOST[:PORT]]                       Start the interactive shell
+  client get [OPTIONS] HOST[:PORT] REMOTE [LOCAL]
+  client get [OPTIONS] tftp://HOST[:PORT]/REMOTE [LOCAL]
+  client put [OPTIONS] HOST[:PORT] LOCAL [REMOTE]
+  client put [OPTIONS] LOCAL tftp://HOST[:PORT]/REMOTE
+
+HOST is a host name, an IPv4 or an IPv6 address, an IPv6 address with a
+port is written in brackets such as [::1]:69. PORT defaults to 69. Every
+address a name resolves to is tried in turn until one responds. REMOTE in a
+URL is percent-decoded, tftp://HOST/a%20b names the file \"a b\".
+
+Options:
+  -m, --mode <MODE>            Transfer mode, octet or netascii [default: octet]
+  -b, --blksize <BYTES>        Block size to request from the server
+  -w, --windowsize <BLOCKS>    Window size to request from the server
+  -t, --timeout <SECS>         Seconds to wait before retransmitting [default: 5]
+  -r, --retries <COUNT>        Retransmissions before giving up [default: 5]
+  -v, --verbose                Print more details, may be repeated
+  -q, --quiet                  Print nothing but errors, no progress bar
+  -h, --help                   Print this help
+
+Exit status:
+  0 on success, 1 on a local error, 2 on invalid usage, 3 when the server
+  stops responding, 4 on a protocol violation, 10 plus the TFTP error code
+  when the server reports one of the codes 0 to 8 and 19 for any other code.";
+
+pub const DEFAULT_PORT: u16 = 69;
+const URL_SCHEME: &str = "tftp://";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Get,
+    Put,
+}
+
+// A server as given by the user, resolved when a transfer starts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Server {
+    pub host: String,
+    pub port: u16,
+}
+
+impl fmt::Display for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+pub struct Transfer {
+    pub direction: Direction,
+    pub server: Server,
+    pub remote: String,
+    pub local: String,
+    pub mode: Mode,
+    pub block_size: Option<usize>,
+    pub window_size: Option<u16>,
+    pub timeout: Duration,
+    pub retries: u32,
+    pub verbosity: u8,
+    pub quiet: bool,
+}
+
+pub enum Command {
+    Interactive(Option<Server>),
+    Transfer(Transfer),
+    Help,
+}
+
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut args = args.into_iter();
+    let direction = match args.next().as_deref() {
+        None => return Ok(Command::Interactive(None)),
+        Some("get") => Direction::Get,
+        Some("put") => Direction::Put,
+        Some("-h") | Some("--help") => return Ok(Command::Help),
+        Some(option) if option.starts_with('-') => {
+            return Err(format!("unknown option {}", option));
+        }
+        Some(host) => {
+            let server = parse_server(host)?;
+            if let Some(extra) = args.next() {
+                return Err(format!("unexpected argument {}", extra));
+            }
+            return Ok(Command::Interactive(Some(server)));
+        }
+    };
+
+    let mut mode = Mode::Octet;
+    let mut block_size = None;
+    let mut window_size = None;
+    let mut timeout = DEFAULT_TIMEOUT;
+    let mut retries = DEFAULT_RETRIES;
+    let mut verbosity = 0;
+    let mut quiet = false;
+    let mut positional = Vec::new();
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            positional.push(arg);
+            continue;
+        }
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} requires a value", flag))
+        };
+        match flag.as_str() {
+            "-m" | "--mode" => {
+                let value = value()?;
+                mode = Mode::parse(&value).ok_or_else(|| format!("unknown mode {}", value))?;
+            }
+            "-b" | "--blksize" => block_size = Some(parse_number(&flag, &value()?)?),
+            "-w" | "--windowsize" => window_size = Some(parse_number(&flag, &value()?)?),
+            "-t" | "--timeout" => {
+                let seconds: u64 = parse_number(&flag, &value()?)?;
+                if seconds == 0 {
+                    return Err("timeout must be at least 1 second".to_string());
+                }
+                timeout = Duration::from_secs(seconds);
+            }
+            "-r" | "--retries" => retries = parse_number(&flag, &value()?)?,
+            "-h" | "--help" => return Ok(Command::Help),
+            "-q" | "--quiet" => quiet = true,
+            "--verbose" => verbosity += 1,
+            // -v, -vv, ...
+            _ if flag[1..].chars().all(|flag| flag == 'v') => verbosity += flag.len() as u8 - 1,
+            _ => return Err(format!("unknown option {}", flag)),
+        }
+    }
+
+    let (server, remote, local) = match (direction, positional.as_slice()) {
+        (Direction::Get, [url, rest @ ..]) if is_url(url) && rest.len() <= 1 => {
+            let (server, remote) = parse_url(url)?;
+            let local = rest.first().cloned().unwrap_or_else(|| base_name(&remote));
+            (server, remote, local)
+        }
+        (Direction::Put, [local, url]) if is_url(url) => {
+            let (server, remote) = parse_url(url)?;
+            (server, remote, local.clone())
+        }
+        // the optional second name defaults to the last component of the first
+        (Direction::Get, [host, remote, rest @ ..]) if rest.len() <= 1 => {
+            let local = rest.first().cloned().unwrap_or_else(|| base_name(remote));
+            (parse_server(host)?, remote.clone(), local)
+        }
+        (Direction::Put, [host, local, rest @ ..]) if rest.len() <= 1 => {
+            let remote = rest.first().cloned().unwrap_or_else(|| base_name(local));
+            (parse_server(host)?, remote, local.clone())
+        }
+        (_, []) => return Err("missing HOST".to_string()),
+        (_, [_]) => return Err("missing file name".to_string()),
+        (_, [.., extra]) => return Err(format!("unexpected argument {}", extra)),
+    };
+
+    Ok(Command::Transfer(Transfer {
+        direction,
+        server,
+        remote,
+        local,
+        mode,
+        block_size,
+        window_size,
+        timeout,
+        retries,
+        verbosity,
+        quiet,
+    }))
+}
+
+pub fn base_name(path: &str) -> String {
+    path.rsplit(['/', '\\']).next().unwrap_or(path).to_string()
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {} for {}", value, flag))
+}
+
+// Splits `host`, `host:port`, `[v6]:port` or a bare IPv6 address into the
+// host and the port, which defaults to 69. Names are resolved later.
+pub fn parse_server(server: &str) -> Result<Server, String> {
+    let (host, port) = if let Some(bracketed) = server.strip_prefix('[') {
+        let (host, rest) = bracketed
+            .split_once(']')
+            .ok_or_else(|| format!("invalid server address {}", server))?;
+        match rest {
+            "" => (host, None),
+            _ => match rest.strip_prefix(':') {
+                Some(port) => (host, Some(port)),
+                None => return Err(format!("invalid server address {}", server)),
+            },
+        }
+    } else if server.parse::<IpAddr>().is_ok() {
+        (server, None)
+    } else {
+        match server.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (server, None),
+        }
+    };
+    if host.is_empty() {
+        return Err(format!("invalid server address {}", server));
+    }
+    let port = match port {
+        Some(port) => port
+            .parse::<u16>()
+            .map_err(|_| format!("invalid port {} in {}", port, server))?,
+        None => DEFAULT_PORT,
+    };
+    Ok(Server {
+        host: host.to_string(),
+        port,
+    })
+}
+
+fn is_url(argument: &str) -> bool {
+    argument
+        .get(..URL_SCHEME.len())
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case(URL_SCHEME))
+}
+
+// Splits `tftp://host[:port]/path` into the server and the remote file name,
+// which is percent-decoded.
+fn parse_url(url: &str) -> Result<(Server, String), String> {
+    let rest = &url[URL_SCHEME.len()..];
+    let (server, path) = rest
+        .split_once('/')
+        .ok_or_else(|| format!("missing file name in {}", url))?;
+    if path.is_empty() {
+        return Err(format!("missing file name in {}", url));
+    }
+    let path = percent_decode(path).ok_or_else(|| format!("invalid file name in {}", url))?;
+    Ok((parse_server(server)?, path))
+}
+
+// Refuses a `%` not followed by two hex digits and escapes that decode to
+// invalid UTF-8.
+fn percent_decode(path: &str) -> Option<String> {
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut bytes = path.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let high = (bytes.next()? as char).to_digit(16)?;
+            let low = (bytes.next()? as char).to_digit(16)?;
+            decoded.push((high * 16 + low) as u8);
+        } else {
+            decoded.push(byte);
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
@@ -1,223 +1,162 @@
-use std::fs::File;
-use std::io;
-use std::io::{BufRead, BufWriter, Seek, SeekFrom, Write};
-use std::net::UdpSocket;
-use tftp_libs::{extract_message, get_read_file_info, send_tftp_message, Message, TftpSessionInfo};
-
-const SERVER_HOST: &str = "127.0.0.1:69";
+mod cli;
+mod progress;
+mod shell;
+
+use cli::{Command, Direction, Transfer};
+use log::LevelFilter;
+use progress::ProgressBar;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use tftp_libs::client::TftpClient;
+use tftp_libs::{logging, ErrorCode, TftpError};
 
 fn main() {
-    let socket = UdpSocket::bind("127.0.0.1:34252").expect("Failed to bind to udp socket");
-
-    println!("Welcome to a simple TFTP client!");
-    loop {
-        println!("*****************************************");
-        println!("Select one of the options to do something");
-        println!("1. GET a file from the server");
-        println!("2. PUT a file to the server");
-        println!("0. Exit");
-
-        let mut choice = String::new();
-        io::stdin()
-            .read_line(&mut choice)
-            .expect("Failed to read line");
-
-        let guess: u32 = match choice.trim().parse() {
-            Ok(num) => num,
-            Err(_) => continue,
-        };
-
-        match guess {
-            0 => {
-                println!("Sayonara ...");
-                break;
-            }
-            1 => get_file(&socket),
-            2 => put_file(&socket),
-            _ => {
-                println!("Invalid option...");
-                continue;
+    let command = match cli::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(error) => {
+            eprintln!("error: {}\n\n{}", error, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    let _ = logging::init(LevelFilter::Warn);
+    match command {
+        Command::Help => println!("{}", cli::USAGE),
+        Command::Interactive(server) => shell::Shell::new(server).run(),
+        Command::Transfer(transfer) => {
+            if let Err(error) = run_transfer(&transfer) {
+                eprintln!("error: {}", describe(&error));
+                process::exit(exit_code(&error));
             }
         }
     }
 }
 
-fn get_file(udp_socket: &UdpSocket) {
-    println!("Download mode:");
-
-    let file_name = get_file_name();
-    let mut session_info = TftpSessionInfo::new();
-    session_info.file_name = file_name.clone();
-
-    // send a read request with the file name
-    send_tftp_message(
-        udp_socket,
-        Message::ReadRequest {
-            file_name: file_name.trim().to_string(),
-            mode: "default".to_string(), //TODO: implement mode
-        },
-        SERVER_HOST,
-    );
-
-    do_work(udp_socket, &mut session_info);
-}
-
-fn put_file(udp_socket: &UdpSocket) {
-    println!("Upload mode:");
-    let file_name = get_file_name();
-
-    let file_result = get_read_file_info(file_name.clone());
-    let (reader, file_length) = match file_result {
-        Ok((reader, length)) => (reader, length),
-        Err(error) => {
-            eprintln!("Error reading file: {}", error);
-            return; // nothing else to do here
-        }
+fn run_transfer(transfer: &Transfer) -> Result<(), TftpError> {
+    if transfer.quiet {
+        log::set_max_level(LevelFilter::Error);
+    } else {
+        log::set_max_level(log_level(transfer.verbosity));
+    }
+    let server = &transfer.server;
+    let mut builder = TftpClient::builder((server.host.as_str(), server.port))
+        .mode(transfer.mode)
+        .block_size(transfer.block_size)
+        .window_size(transfer.window_size)
+        .timeout(transfer.timeout)
+        .retries(transfer.retries);
+    // only drawn on a terminal, scripts get the plain output
+    let mut progress_bar = None;
+    if !transfer.quiet && io::stderr().is_terminal() {
+        let bar = Arc::new(ProgressBar::new());
+        let updated = bar.clone();
+        builder = builder.progress(move |progress| updated.update(progress));
+        progress_bar = Some(bar);
+    }
+    let client = builder.build()?;
+    let result = match transfer.direction {
+        Direction::Get => download(&client, &transfer.remote, &transfer.local),
+        Direction::Put => upload(&client, &transfer.local, &transfer.remote),
     };
-
-    let mut session_info = TftpSessionInfo::new();
-    session_info.file_name = file_name.clone();
-    session_info.reader = Some(reader);
-    session_info.block_count = ((file_length / 512) + 1u64) as usize;
-
-    // send a write request with the file name
-    send_tftp_message(
-        udp_socket,
-        Message::WriteRequest {
-            file_name: file_name.to_string(),
-            mode: "default".to_string(), //TODO: implement mode
-        },
-        SERVER_HOST,
-    );
-
-    do_work(udp_socket, &mut session_info);
+    if let Some(progress_bar) = progress_bar {
+        progress_bar.finish();
+    }
+    let bytes = result?;
+    if transfer.verbosity > 0 && !transfer.quiet {
+        println!("Transferred {} bytes", bytes);
+    }
+    Ok(())
 }
 
-fn do_work(udp_socket: &UdpSocket, session_info: &mut TftpSessionInfo) {
-    let mut buffer = [0; 520];
-    loop {
-        let receive_result = udp_socket.recv_from(&mut buffer);
-        if receive_result.is_err() {
-            println!("Failed to receive data");
-            continue;
-        }
-        let (amt, _) = receive_result.unwrap();
-        let buf = &mut buffer[..amt];
-        let completed = handle_request(&udp_socket, buf, session_info);
-        if completed {
-            println!("*****************************************");
-            break;
-        }
+// Warnings only by default, -v adds the protocol details and -vv every packet.
+pub fn log_level(verbosity: u8) -> LevelFilter {
+    match verbosity {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
     }
 }
-fn get_file_name<'a>() -> String {
-    println!("Enter file name: ");
-    let mut file_name = String::new();
-    io::stdin()
-        .read_line(&mut file_name)
-        .expect("Failed to read line");
 
-    file_name.trim().to_string()
+// Downloads into a file next to `local` that replaces it once the transfer
+// succeeded, so a failed download leaves an existing file alone. Devices and
+// pipes are written to directly.
+fn download(client: &TftpClient, remote: &str, local: &str) -> Result<u64, TftpError> {
+    let local = Path::new(local);
+    if fs::metadata(local).is_ok_and(|metadata| !metadata.is_file()) {
+        let file = OpenOptions::new().write(true).open(local)?;
+        return client.get(remote, BufWriter::new(file));
+    }
+    let partial = partial_path(local);
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&partial)?;
+    let result = client
+        .get(remote, BufWriter::new(file))
+        .and_then(|bytes| Ok(fs::rename(&partial, local).map(|_| bytes)?));
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
 }
 
-fn handle_request(
-    udp_socket: &UdpSocket,
-    buffer: &[u8],
-    session_info: &mut TftpSessionInfo,
-) -> bool {
-    let message = extract_message(buffer);
-    match message {
-        Message::ReadRequest { file_name, mode } => {
-            // TODO client doesn't need to handle read requests
-            println!("received request to read {} with mode {}", file_name, mode);
-            panic!("Client received read request")
-        }
-        Message::WriteRequest { file_name, mode } => {
-            // TODO client doesn't need to handle read requests
-            println!("received request to write {} with mode {}", file_name, mode);
-            panic!("Client received write request")
-        }
-        Message::Data {
-            block_number,
-            data,
-            length,
-        } => {
-            println!(
-                "received data of length {} for block {}",
-                length, block_number
-            );
-            if block_number == 1 {
-                let file =
-                    File::create(session_info.file_name.clone()).expect("Error creating file");
-                let writer = BufWriter::new(file);
-                session_info.writer = Some(writer);
-            }
-            //write the contents to file
-            session_info
-                .writer
-                .as_mut()
-                .expect("Writer not set")
-                .write(data)
-                .expect("Error writing chunk to file");
-            // TODO handle write error and re-request the block??
-            send_tftp_message(udp_socket, Message::Ack { block_number }, SERVER_HOST);
-            println!("sent back ack for block number {}", block_number);
-            if length < 512 {
-                println!("Download Complete");
-                return true;
-            }
-            return false;
-        }
-        Message::Ack { block_number } => {
-            println!("received ack of block {}", block_number);
-            // check if we are done
-            if block_number == session_info.block_count as u16 {
-                println!(
-                    "Received last ack for file name: {}",
-                    session_info.file_name
-                );
-                println!("Download Complete");
-                return true;
-            }
-
-            println!("Reading next block of file: {}", session_info.file_name);
-            let reader = session_info.reader.as_mut().expect("Reader not found");
-            if block_number != 0 {
-                reader
-                    .seek(SeekFrom::Current(512))
-                    .expect("Unable to seek file"); // move to next block
-            }
-            let contents = reader.fill_buf().expect("Unable to read file contents");
-            let block_number = block_number + 1;
+// A hidden name in the same directory, for the rename to stay on one file
+// system.
+fn partial_path(local: &Path) -> PathBuf {
+    let name = local.file_name().unwrap_or_default().to_string_lossy();
+    local.with_file_name(format!(".{}.{}.part", name, process::id()))
+}
 
-            //TODO send back error if block is out of range
-            send_tftp_message(
-                udp_socket,
-                Message::Data {
-                    block_number,
-                    data: contents[0..contents.len()].as_ref(),
-                    length: contents.len(),
-                },
-                SERVER_HOST,
-            );
+fn upload(client: &TftpClient, local: &str, remote: &str) -> Result<u64, TftpError> {
+    let file = File::open(local)?;
+    let size = file.metadata()?.len();
+    client.put_with_size(BufReader::new(file), remote, Some(size))
+}
 
-            println!(
-                "Sent back block number {} of {} bytes",
-                block_number,
-                contents.len()
-            );
-            if contents.len() < 512 {
-                println!("Sent back last block to client");
-            }
-            false
-        }
-        Message::Error {
+// Error packets from the server are spelled out, with what the server said
+// kept for reference.
+pub fn describe(error: &TftpError) -> String {
+    match error {
+        TftpError::Remote {
             error_code,
             error_message,
-        } => {
-            eprintln!("received error message : {}", error_message);
-            eprintln!("received error code : {}", error_code);
-            true
-        }
+        } => format!(
+            "{} (TFTP error {}: {})",
+            explain(*error_code),
+            error_code,
+            error_message
+        ),
+        error => error.to_string(),
+    }
+}
+
+fn explain(error_code: ErrorCode) -> &'static str {
+    match error_code {
+        ErrorCode::NotDefined => "the server reported an error",
+        ErrorCode::FileNotFound => "the file does not exist on the server",
+        ErrorCode::AccessViolation => "the server denied access to the file",
+        ErrorCode::DiskFull => "the server ran out of disk space",
+        ErrorCode::IllegalOperation => "the server rejected a packet as an illegal operation",
+        ErrorCode::UnknownTransferId => "the server does not know this transfer",
+        ErrorCode::FileAlreadyExists => "the file already exists on the server",
+        ErrorCode::NoSuchUser => "the server does not know the user",
+        ErrorCode::OptionNegotiation => "the server refused the requested options",
+        ErrorCode::Unknown(_) => "the server reported an error with an unknown code",
+    }
+}
+
+// Each code of the RFCs gets its own status, anything else shares one.
+fn exit_code(error: &TftpError) -> i32 {
+    match error {
+        TftpError::Remote {
+            error_code: ErrorCode::Unknown(_),
+            ..
+        } => 19,
+        TftpError::Remote { error_code, .. } => 10 + u16::from(*error_code) as i32,
+        TftpError::TimedOut(_) => 3,
+        TftpError::Protocol(_) => 4,
+        _ => 1,
     }
 }
@@ -0,0 +1,104 @@
+use std::io::{self, Write};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tftp_libs::transfer::TransferProgress;
+
+const WIDTH: usize = 30;
+// redrawing on every block would cost more than the transfer itself
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+// A single line on standard error, redrawn in place as blocks go through.
+pub struct ProgressBar {
+    last_drawn: Mutex<Option<Instant>>,
+}
+
+impl ProgressBar {
+    pub fn new() -> Self {
+        ProgressBar {
+            last_drawn: Mutex::new(None),
+        }
+    }
+
+    pub fn update(&self, progress: &TransferProgress) {
+        let mut last_drawn = self
+            .last_drawn
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let done = progress.total.is_some_and(|total| progress.bytes >= total);
+        if !done && last_drawn.is_some_and(|drawn| drawn.elapsed() < REDRAW_INTERVAL) {
+            return;
+        }
+        *last_drawn = Some(Instant::now());
+        let _ = write!(io::stderr(), "\r{}\x1b[K", render(progress));
+    }
+
+    // Moves past the bar once the transfer is over, whatever its outcome.
+    pub fn finish(&self) {
+        let last_drawn = self
+            .last_drawn
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if last_drawn.is_some() {
+            let _ = writeln!(io::stderr());
+        }
+    }
+}
+
+fn render(progress: &TransferProgress) -> String {
+    let rate = format!("{}/s", format_bytes(progress.rate() as u64));
+    match (progress.fraction(), progress.total) {
+        (Some(fraction), Some(total)) => {
+            let filled = (fraction * WIDTH as f64) as usize;
+            let eta = progress
+                .eta()
+                .map(format_duration)
+                .unwrap_or_else(|| "--:--".to_string());
+            format!(
+                "{} [{}{}] {:>3}% {}/{} {} ETA {}",
+                progress.file_name,
+                "=".repeat(filled),
+                " ".repeat(WIDTH - filled),
+                (fraction * 100.0) as u32,
+                format_bytes(progress.bytes),
+                format_bytes(total),
+                rate,
+                eta
+            )
+        }
+        _ => format!(
+            "{} {} {} {}",
+            progress.file_name,
+            format_bytes(progress.bytes),
+            rate,
+            format_duration(progress.elapsed)
+        ),
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
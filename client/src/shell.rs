@@ -0,0 +1,339 @@
+use crate::cli::{self, base_name, Server};
+use crate::{describe, download, log_level, upload};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::env;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tftp_libs::client::TftpClient;
+use tftp_libs::{Mode, TftpError, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE};
+
+const PROMPT: &str = "tftp> ";
+const HISTORY_FILE: &str = ".tftp_history";
+
+const HELP: &str = "Commands may be abbreviated.  Commands are:
+
+connect         connect to remote tftp
+mode            set file transfer mode
+put             send file
+get             receive file
+quit            exit tftp
+verbose         toggle verbose mode
+trace           toggle packet tracing
+status          show current status
+binary          set mode to octet
+ascii           set mode to netascii
+rexmt           set per-packet retransmission timeout
+timeout         set total retransmission timeout
+blksize         set the block size to request
+?               print help information";
+
+const COMMANDS: [&str; 15] = [
+    "connect", "mode", "put", "get", "quit", "exit", "verbose", "trace", "status", "binary",
+    "ascii", "rexmt", "timeout", "blksize", "help",
+];
+
+// Settings of the classic BSD tftp client, applied to every transfer.
+pub struct Shell {
+    server: Option<Server>,
+    mode: Mode,
+    verbose: bool,
+    trace: bool,
+    retransmit_timeout: Duration,
+    total_timeout: Duration,
+    block_size: Option<usize>,
+}
+
+impl Shell {
+    pub fn new(server: Option<Server>) -> Self {
+        Shell {
+            server,
+            mode: Mode::Netascii,
+            verbose: false,
+            trace: false,
+            retransmit_timeout: Duration::from_secs(5),
+            total_timeout: Duration::from_secs(25),
+            block_size: None,
+        }
+    }
+
+    pub fn run(&mut self) {
+        let mut editor = match DefaultEditor::new() {
+            Ok(editor) => editor,
+            Err(error) => {
+                eprintln!("Unable to start the shell: {}", error);
+                return;
+            }
+        };
+        let history = history_file();
+        if let Some(history) = &history {
+            let _ = editor.load_history(history);
+        }
+        loop {
+            let line = match editor.readline(PROMPT) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    break;
+                }
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let _ = editor.add_history_entry(line.as_str());
+            if !self.execute(&words) {
+                break;
+            }
+        }
+        if let Some(history) = &history {
+            let _ = editor.save_history(history);
+        }
+    }
+
+    // Runs a single command, returns false once the shell should exit.
+    fn execute(&mut self, words: &[&str]) -> bool {
+        let command = match resolve_command(words[0]) {
+            Ok(command) => command,
+            Err(message) => {
+                println!("{}", message);
+                return true;
+            }
+        };
+        let arguments = &words[1..];
+        match command {
+            "connect" => self.connect(arguments),
+            "mode" => self.set_mode(arguments),
+            "binary" => self.set_mode(&["octet"]),
+            "ascii" => self.set_mode(&["netascii"]),
+            "get" => self.get(arguments),
+            "put" => self.put(arguments),
+            "verbose" => {
+                self.verbose = !self.verbose;
+                println!("Verbose mode {}.", on_off(self.verbose));
+            }
+            "trace" => {
+                self.trace = !self.trace;
+                println!("Packet tracing {}.", on_off(self.trace));
+            }
+            "status" => self.status(),
+            "rexmt" => {
+                if let Some(seconds) = parse_seconds("rexmt", arguments) {
+                    self.retransmit_timeout = seconds;
+                }
+            }
+            "timeout" => {
+                if let Some(seconds) = parse_seconds("timeout", arguments) {
+                    self.total_timeout = seconds;
+                }
+            }
+            "blksize" => self.set_block_size(arguments),
+            "help" => println!("{}", HELP),
+            "quit" | "exit" => return false,
+            _ => unreachable!(),
+        }
+        true
+    }
+
+    fn connect(&mut self, arguments: &[&str]) {
+        let server = match arguments {
+            [host] => cli::parse_server(host),
+            [host, port] => port
+                .parse::<u16>()
+                .map_err(|_| format!("{}: bad port number", port))
+                .and_then(|port| cli::parse_server(host).map(|server| (server, port)))
+                .map(|(server, port)| Server { port, ..server }),
+            _ => Err("usage: connect host-name [port]".to_string()),
+        };
+        match server {
+            Ok(server) => self.server = Some(server),
+            Err(error) => println!("{}", error),
+        }
+    }
+
+    fn set_mode(&mut self, arguments: &[&str]) {
+        match arguments {
+            [] => println!("Using {} mode to transfer files.", self.mode.as_str()),
+            [mode] => {
+                let mode = match *mode {
+                    "ascii" => Some(Mode::Netascii),
+                    "binary" => Some(Mode::Octet),
+                    mode => Mode::parse(mode),
+                };
+                match mode {
+                    Some(mode) => self.mode = mode,
+                    None => println!("{}: unknown mode", arguments[0]),
+                }
+            }
+            _ => println!("usage: mode [ ascii | netascii | binary | octet ]"),
+        }
+    }
+
+    fn set_block_size(&mut self, arguments: &[&str]) {
+        match arguments.first().map(|size| size.parse::<usize>()) {
+            Some(Ok(size)) if (MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&size) => {
+                self.block_size = Some(size)
+            }
+            Some(_) => println!(
+                "blksize must be between {} and {}",
+                MIN_BLOCK_SIZE, MAX_BLOCK_SIZE
+            ),
+            None => println!("usage: blksize value"),
+        }
+    }
+
+    fn status(&self) {
+        match &self.server {
+            Some(server) => println!("Connected to {}.", server),
+            None => println!("Not connected."),
+        }
+        println!(
+            "Mode: {} Verbose: {} Tracing: {}",
+            self.mode.as_str(),
+            on_off(self.verbose),
+            on_off(self.trace)
+        );
+        println!(
+            "Rexmt-interval: {} seconds, Max-timeout: {} seconds",
+            self.retransmit_timeout.as_secs(),
+            self.total_timeout.as_secs()
+        );
+        match self.block_size {
+            Some(block_size) => println!("Blksize: {}", block_size),
+            None => println!("Blksize: default"),
+        }
+    }
+
+    // get file, get remote local, or get file1 file2 ... fileN
+    fn get(&self, arguments: &[&str]) {
+        let transfers: Vec<(String, String)> = match arguments {
+            [] => {
+                println!("usage: get file [localname] or get file1 file2 ... fileN");
+                return;
+            }
+            [remote] => vec![(remote.to_string(), base_name(remote))],
+            [remote, local] => vec![(remote.to_string(), local.to_string())],
+            files => files
+                .iter()
+                .map(|remote| (remote.to_string(), base_name(remote)))
+                .collect(),
+        };
+        for (remote, local) in transfers {
+            self.transfer(|client| download(client, &remote, &local), "Received");
+        }
+    }
+
+    // put file, put local remote, or put file1 file2 ... fileN remote-directory
+    fn put(&self, arguments: &[&str]) {
+        let transfers: Vec<(String, String)> = match arguments {
+            [] => {
+                println!(
+                    "usage: put file [remotename] or put file1 file2 ... fileN remote-directory"
+                );
+                return;
+            }
+            [local] => vec![(local.to_string(), base_name(local))],
+            [local, remote] => vec![(local.to_string(), remote.to_string())],
+            [files @ .., directory] => files
+                .iter()
+                .map(|local| {
+                    let remote =
+                        format!("{}/{}", directory.trim_end_matches('/'), base_name(local));
+                    (local.to_string(), remote)
+                })
+                .collect(),
+        };
+        for (local, remote) in transfers {
+            self.transfer(|client| upload(client, &local, &remote), "Sent");
+        }
+    }
+
+    fn transfer<F: FnOnce(&TftpClient) -> Result<u64, TftpError>>(&self, transfer: F, verb: &str) {
+        let server = match &self.server {
+            Some(server) => server,
+            None => {
+                println!("No target machine specified.");
+                return;
+            }
+        };
+        let retries =
+            (self.total_timeout.as_secs() / self.retransmit_timeout.as_secs().max(1)).max(1);
+        let verbosity = if self.trace { 2 } else { self.verbose as u8 };
+        log::set_max_level(log_level(verbosity));
+        let result = TftpClient::builder((server.host.as_str(), server.port))
+            .mode(self.mode)
+            .block_size(self.block_size)
+            .timeout(self.retransmit_timeout)
+            .retries(retries as u32)
+            .build();
+        let client = match result {
+            Ok(client) => client,
+            Err(error) => {
+                println!("{}", error);
+                return;
+            }
+        };
+        let started = Instant::now();
+        match transfer(&client) {
+            Ok(bytes) => {
+                if self.verbose {
+                    println!(
+                        "{} {} bytes in {:.1} seconds",
+                        verb,
+                        bytes,
+                        started.elapsed().as_secs_f64()
+                    );
+                }
+            }
+            Err(error) => println!("Error: {}", describe(&error)),
+        }
+    }
+}
+
+// Commands may be abbreviated to any unambiguous prefix.
+fn resolve_command(word: &str) -> Result<&'static str, String> {
+    if word == "?" {
+        return Ok("help");
+    }
+    if let Some(command) = COMMANDS.iter().find(|command| **command == word) {
+        return Ok(command);
+    }
+    let matches: Vec<&'static str> = COMMANDS
+        .iter()
+        .copied()
+        .filter(|command| command.starts_with(word))
+        .collect();
+    match matches.as_slice() {
+        [command] => Ok(command),
+        [] => Err("?Invalid command".to_string()),
+        _ => Err("?Ambiguous command".to_string()),
+    }
+}
+
+fn parse_seconds(command: &str, arguments: &[&str]) -> Option<Duration> {
+    match arguments.first().map(|seconds| seconds.parse::<u64>()) {
+        Some(Ok(seconds)) if seconds > 0 => Some(Duration::from_secs(seconds)),
+        Some(_) => {
+            println!("{}: bad value", arguments[0]);
+            None
+        }
+        None => {
+            println!("usage: {} value", command);
+            None
+        }
+    }
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+fn history_file() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
@@ -0,0 +1,177 @@
+// The client is a binary without a library, so its argument parser is
+// compiled into this test on its own.
+#[allow(dead_code)]
+#[path = "../src/cli.rs"]
+mod cli;
+
+use cli::{parse_server, Command, Direction, Server, DEFAULT_PORT};
+
+fn server(host: &str, port: u16) -> Server {
+    Server {
+        host: host.to_string(),
+        port,
+    }
+}
+
+// The server, remote and local file names of a command line.
+fn transfer(args: &[&str]) -> Result<(Direction, Server, String, String), String> {
+    match cli::parse(args.iter().map(|arg| arg.to_string()))? {
+        Command::Transfer(transfer) => Ok((
+            transfer.direction,
+            transfer.server,
+            transfer.remote,
+            transfer.local,
+        )),
+        _ => panic!("{:?} is not a transfer", args),
+    }
+}
+
+#[test]
+fn servers_are_split_into_host_and_port() {
+    assert_eq!(
+        parse_server("tftp.example.com"),
+        Ok(server("tftp.example.com", DEFAULT_PORT))
+    );
+    assert_eq!(
+        parse_server("tftp.example.com:6969"),
+        Ok(server("tftp.example.com", 6969))
+    );
+    assert_eq!(parse_server("10.0.0.1"), Ok(server("10.0.0.1", 69)));
+    assert_eq!(parse_server("10.0.0.1:70"), Ok(server("10.0.0.1", 70)));
+    // a bare IPv6 address has no port, its last group is not one
+    assert_eq!(parse_server("::1"), Ok(server("::1", 69)));
+    assert_eq!(parse_server("fe80::1:69"), Ok(server("fe80::1:69", 69)));
+    assert_eq!(parse_server("[::1]"), Ok(server("::1", 69)));
+    assert_eq!(parse_server("[::1]:6969"), Ok(server("::1", 6969)));
+}
+
+#[test]
+fn invalid_servers_are_refused() {
+    assert_eq!(
+        parse_server("host:tftp"),
+        Err("invalid port tftp in host:tftp".to_string())
+    );
+    assert_eq!(
+        parse_server("host:65536"),
+        Err("invalid port 65536 in host:65536".to_string())
+    );
+    assert_eq!(
+        parse_server("[::1]:"),
+        Err("invalid port  in [::1]:".to_string())
+    );
+    assert_eq!(
+        parse_server("[::1"),
+        Err("invalid server address [::1".to_string())
+    );
+    assert_eq!(
+        parse_server("[::1]69"),
+        Err("invalid server address [::1]69".to_string())
+    );
+    assert_eq!(
+        parse_server(":69"),
+        Err("invalid server address :69".to_string())
+    );
+}
+
+#[test]
+fn servers_display_as_they_are_parsed() {
+    assert_eq!(server("::1", 69).to_string(), "[::1]:69");
+    assert_eq!(server("10.0.0.1", 69).to_string(), "10.0.0.1:69");
+}
+
+#[test]
+fn urls_name_the_server_and_file() {
+    assert_eq!(
+        transfer(&["get", "tftp://[::1]:69/path"]),
+        Ok((
+            Direction::Get,
+            server("::1", 69),
+            "path".to_string(),
+            "path".to_string()
+        ))
+    );
+    assert_eq!(
+        transfer(&["get", "TFTP://host/pxe/boot.img", "local.img"]),
+        Ok((
+            Direction::Get,
+            server("host", 69),
+            "pxe/boot.img".to_string(),
+            "local.img".to_string()
+        ))
+    );
+    assert_eq!(
+        transfer(&["put", "local.img", "tftp://10.0.0.1:6969/upload/a.img"]),
+        Ok((
+            Direction::Put,
+            server("10.0.0.1", 6969),
+            "upload/a.img".to_string(),
+            "local.img".to_string()
+        ))
+    );
+}
+
+#[test]
+fn url_paths_are_percent_decoded() {
+    let remote = |url: &str| transfer(&["get", url]).map(|(_, _, remote, _)| remote);
+    assert_eq!(remote("tftp://h/a%20b"), Ok("a b".to_string()));
+    assert_eq!(remote("tftp://h/%E2%82%ac.txt"), Ok("€.txt".to_string()));
+    assert_eq!(remote("tftp://h/100%25"), Ok("100%".to_string()));
+    assert_eq!(
+        remote("tftp://h/a%2"),
+        Err("invalid file name in tftp://h/a%2".to_string())
+    );
+    assert_eq!(
+        remote("tftp://h/a%zz"),
+        Err("invalid file name in tftp://h/a%zz".to_string())
+    );
+    assert_eq!(
+        remote("tftp://h/%ff"),
+        Err("invalid file name in tftp://h/%ff".to_string())
+    );
+}
+
+#[test]
+fn invalid_urls_are_refused() {
+    assert_eq!(
+        transfer(&["get", "tftp://host"]),
+        Err("missing file name in tftp://host".to_string())
+    );
+    assert_eq!(
+        transfer(&["get", "tftp://host/"]),
+        Err("missing file name in tftp://host/".to_string())
+    );
+    assert_eq!(
+        transfer(&["get", "tftp://host:port/file"]),
+        Err("invalid port port in host:port".to_string())
+    );
+    assert_eq!(
+        transfer(&["put", "local.img", "tftp://[::1]:99999/file"]),
+        Err("invalid port 99999 in [::1]:99999".to_string())
+    );
+}
+
+#[test]
+fn host_arguments_default_the_other_name() {
+    assert_eq!(
+        transfer(&["get", "[::1]:6969", "pxe/boot.img"]),
+        Ok((
+            Direction::Get,
+            server("::1", 6969),
+            "pxe/boot.img".to_string(),
+            "boot.img".to_string()
+        ))
+    );
+    assert_eq!(
+        transfer(&["put", "host", "dir/local.img"]),
+        Ok((
+            Direction::Put,
+            server("host", 69),
+            "local.img".to_string(),
+            "dir/local.img".to_string()
+        ))
+    );
+    assert_eq!(
+        transfer(&["get", "host"]),
+        Err("missing file name".to_string())
+    );
+}
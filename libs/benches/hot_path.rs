@@ -0,0 +1,131 @@
+// Downloads files over loopback between a server and a client running in this
+// process, counting the heap allocations both of them make once the transfer
+// is under way. Past the first few blocks, moving a block along must not
+// allocate at all.
+//
+//     cargo bench -p tftp_libs --bench hot_path
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tftp_libs::client::TftpClient;
+use tftp_libs::server::TftpServer;
+use tftp_libs::Mode;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const BLOCKS: u64 = 10_000;
+// blocks it takes for the window and the buffers to reach their final size
+const WARM_UP: u64 = 64;
+
+fn main() {
+    let root = std::env::temp_dir().join(format!("tftp_hot_path_{}", process::id()));
+    fs::create_dir_all(&root).expect("failed to create the server root");
+    let mut allocations = 0;
+    for (block_size, window_size, mode) in [
+        (512, 1, Mode::Octet),
+        (1428, 1, Mode::Octet),
+        (1428, 16, Mode::Octet),
+        (1428, 16, Mode::Netascii),
+    ] {
+        allocations += download(&root, block_size, window_size, mode);
+    }
+    let _ = fs::remove_dir_all(&root);
+    if allocations > 0 {
+        eprintln!(
+            "error: {} allocations once the transfers were under way",
+            allocations
+        );
+        process::exit(1);
+    }
+}
+
+// Returns the allocations made between the end of the warm up and the last
+// block.
+fn download(root: &Path, block_size: usize, window_size: u16, mode: Mode) -> u64 {
+    let file_name = format!("{}x{}.{}", block_size, window_size, mode.as_str());
+    // one byte more than the blocks for a short last block
+    let size = block_size as u64 * BLOCKS + 1;
+    let mut file = File::create(root.join(&file_name)).expect("failed to create the file");
+    io::copy(&mut io::repeat(0x5a).take(size), &mut file).expect("failed to write the file");
+
+    let mut server = TftpServer::builder()
+        .address("127.0.0.1:0")
+        .root(root)
+        .max_window_size(window_size)
+        .build()
+        .expect("failed to start the server");
+    let address = server.local_addrs()[0];
+    let handle = server.handle();
+    let serving = thread::spawn(move || server.run());
+
+    // allocations counted at the end of the warm up and at the latest block
+    let counts = Arc::new([AtomicU64::new(0), AtomicU64::new(0)]);
+    let recorded = counts.clone();
+    let warmed_up = block_size as u64 * WARM_UP;
+    let client = TftpClient::builder(address)
+        .block_size(Some(block_size))
+        .window_size(Some(window_size))
+        .mode(mode)
+        .progress(move |progress| {
+            let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+            if progress.bytes <= warmed_up {
+                recorded[0].store(allocations, Ordering::Relaxed);
+            }
+            recorded[1].store(allocations, Ordering::Relaxed);
+        })
+        .build()
+        .expect("failed to create the client");
+    let started = Instant::now();
+    let bytes = client
+        .get(&file_name, io::sink())
+        .expect("failed to download the file");
+    let elapsed = started.elapsed();
+    handle.shutdown(Duration::ZERO);
+    serving.join().expect("the server panicked");
+    assert_eq!(bytes, size);
+
+    let allocations = counts[1].load(Ordering::Relaxed) - counts[0].load(Ordering::Relaxed);
+    println!(
+        "{:<8} blksize {:>4} windowsize {:>2}: {:>6.0} ns per block, {} allocations over {} blocks",
+        mode.as_str(),
+        block_size,
+        window_size,
+        elapsed.as_nanos() as f64 / (BLOCKS + 1) as f64,
+        allocations,
+        BLOCKS - WARM_UP
+    );
+    allocations
+}
@@ -0,0 +1,16 @@
+// Any datagram either parses or is refused, and whatever parses can be
+// encoded again into a packet that parses the same.
+//
+//     cargo +nightly fuzz run parse_packet
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tftp_libs::{build_message, extract_message};
+
+fuzz_target!(|packet: &[u8]| {
+    if let Ok(message) = extract_message(packet) {
+        let encoded = build_message(&message).expect("a parsed packet failed to encode");
+        assert_eq!(extract_message(&encoded).ok(), Some(message));
+    }
+});
@@ -0,0 +1,39 @@
+// Feeds datagrams to a fresh server, each from one of a few peers so that
+// they add up to sessions, which must never bring the server down. Replies
+// go out to ports on loopback nobody listens on.
+//
+//     cargo +nightly fuzz run server_request
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tftp_libs::server::TftpServer;
+
+fuzz_target!(|datagrams: Vec<(u8, Vec<u8>)>| {
+    let mut server = TftpServer::builder()
+        .address("127.0.0.1:0")
+        .root(root())
+        .build()
+        .expect("failed to start the server");
+    for (peer, datagram) in datagrams {
+        let source = SocketAddr::from(([127, 0, 0, 1], 40000 + u16::from(peer % 4)));
+        server.handle_datagram(source, &datagram);
+    }
+});
+
+// A file to read and one to upload to, shared by every run.
+fn root() -> &'static Path {
+    static ROOT: OnceLock<PathBuf> = OnceLock::new();
+    ROOT.get_or_init(|| {
+        let root = std::env::temp_dir().join(format!("tftp_fuzz_{}", std::process::id()));
+        fs::create_dir_all(&root).expect("failed to create the server root");
+        fs::write(root.join("read.txt"), "line one\r\nline two\n".repeat(64))
+            .expect("failed to create the file to read");
+        fs::write(root.join("write.bin"), "").expect("failed to create the file to write");
+        root
+    })
+}
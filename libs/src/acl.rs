@@ -0,0 +1,214 @@
+use crate::TftpError;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclAction {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclOperation {
+    Read,
+    Write,
+    Any,
+}
+
+impl FromStr for AclAction {
+    type Err = TftpError;
+
+    fn from_str(action: &str) -> Result<Self, TftpError> {
+        match action {
+            "allow" => Ok(AclAction::Allow),
+            "deny" => Ok(AclAction::Deny),
+            action => Err(invalid_input(format!("unknown action {}", action))),
+        }
+    }
+}
+
+impl FromStr for AclOperation {
+    type Err = TftpError;
+
+    fn from_str(operation: &str) -> Result<Self, TftpError> {
+        match operation {
+            "read" => Ok(AclOperation::Read),
+            "write" => Ok(AclOperation::Write),
+            "any" => Ok(AclOperation::Any),
+            operation => Err(invalid_input(format!("unknown operation {}", operation))),
+        }
+    }
+}
+
+impl AclOperation {
+    fn matches(&self, operation: AclOperation) -> bool {
+        *self == AclOperation::Any || *self == operation
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_length: u8,
+}
+
+impl Cidr {
+    pub fn parse(value: &str) -> Result<Self, TftpError> {
+        let (address, prefix_length) = match value.split_once('/') {
+            Some((address, prefix_length)) => (address, Some(prefix_length)),
+            None => (value, None),
+        };
+        let network: IpAddr = address
+            .parse()
+            .map_err(|_| invalid_input(format!("invalid network address {}", address)))?;
+        let max_length = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix_length = match prefix_length {
+            Some(prefix_length) => prefix_length
+                .parse::<u8>()
+                .ok()
+                .filter(|length| *length <= max_length)
+                .ok_or_else(|| invalid_input(format!("invalid prefix length in {}", value)))?,
+            None => max_length,
+        };
+        Ok(Cidr {
+            network,
+            prefix_length,
+        })
+    }
+
+    // IPv4 clients of a dual-stack socket show up as IPv4-mapped IPv6
+    // addresses and are matched against the IPv4 rules.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_length as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_length as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AclRule {
+    pub action: AclAction,
+    pub operation: AclOperation,
+    pub network: Cidr,
+    pub path_glob: String,
+}
+
+impl AclRule {
+    fn matches(&self, address: IpAddr, operation: AclOperation, path: &str) -> bool {
+        self.operation.matches(operation)
+            && self.network.contains(address)
+            && glob_matches(self.path_glob.as_bytes(), path.as_bytes())
+    }
+}
+
+// Rules are evaluated in order and the first match wins. An empty list allows
+// everything, otherwise a request that matches no rule is denied. Globs are
+// matched against the path relative to the root, without a leading slash or
+// `.` components: `/pxe/./boot.img` is checked as `pxe/boot.img`.
+#[derive(Debug, Clone, Default)]
+pub struct Acl {
+    rules: Vec<AclRule>,
+}
+
+impl Acl {
+    pub fn new() -> Self {
+        Acl { rules: Vec::new() }
+    }
+
+    pub fn add_rule(&mut self, rule: AclRule) {
+        self.rules.push(rule);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // One rule per line: `<allow|deny> <read|write|any> <cidr> <path glob>`.
+    // Blank lines and lines starting with `#` are ignored.
+    pub fn parse(contents: &str) -> Result<Self, TftpError> {
+        let mut acl = Acl::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = parse_rule(line)
+                .map_err(|error| invalid_input(format!("line {}: {}", index + 1, error)))?;
+            acl.add_rule(rule);
+        }
+        Ok(acl)
+    }
+
+    pub fn load<P: AsRef<Path>>(file_name: P) -> Result<Self, TftpError> {
+        Acl::parse(&fs::read_to_string(file_name)?)
+    }
+
+    pub fn check(&self, address: IpAddr, operation: AclOperation, path: &str) -> AclAction {
+        if self.rules.is_empty() {
+            return AclAction::Allow;
+        }
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(address, operation, path))
+            .map(|rule| rule.action)
+            .unwrap_or(AclAction::Deny)
+    }
+}
+
+fn parse_rule(line: &str) -> Result<AclRule, TftpError> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 4 {
+        return Err(invalid_input(
+            "expected `<allow|deny> <read|write|any> <cidr> <path glob>`".to_string(),
+        ));
+    }
+    Ok(AclRule {
+        action: fields[0].parse()?,
+        operation: fields[1].parse()?,
+        network: Cidr::parse(fields[2])?,
+        path_glob: fields[3].to_string(),
+    })
+}
+
+// `*` matches any run of bytes (including `/`) and `?` matches a single byte.
+fn glob_matches(pattern: &[u8], path: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while s < path.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == path[s]) {
+            p += 1;
+            s += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, s));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            s = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|byte| *byte == b'*')
+}
+
+fn invalid_input(message: String) -> TftpError {
+    TftpError::Config(message)
+}
@@ -0,0 +1,144 @@
+use crate::logging::{quote, timestamp};
+use crate::{ErrorCode, TftpError, TftpSessionInfo};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
+
+// How a transfer came to an end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Complete,
+    // the server gave up and told the peer why
+    Failed {
+        error_code: ErrorCode,
+        error_message: String,
+    },
+    // the peer stopped answering
+    TimedOut,
+    // the peer gave up and told the server why
+    Aborted {
+        error_code: ErrorCode,
+        error_message: String,
+    },
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Complete => write!(f, "complete"),
+            Outcome::Failed {
+                error_code,
+                error_message,
+            } => write!(f, "failed with error {}: {}", error_code, error_message),
+            Outcome::TimedOut => write!(f, "timed out"),
+            Outcome::Aborted {
+                error_code,
+                error_message,
+            } => write!(
+                f,
+                "aborted by the peer with error {}: {}",
+                error_code, error_message
+            ),
+        }
+    }
+}
+
+// Append-only record of every finished transfer, one line of key=value
+// fields each. The file stays open so that it keeps working after a chroot,
+// reloading the configuration reopens it.
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, TftpError> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|error| {
+                TftpError::from(error)
+                    .context(format_args!("unable to open audit log {}", path.display()))
+            })?;
+        Ok(AuditLog {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(
+        &self,
+        peer: SocketAddr,
+        session_info: &TftpSessionInfo,
+        outcome: &Outcome,
+    ) -> Result<(), TftpError> {
+        let line = format_record(SystemTime::now(), peer, session_info, outcome);
+        // a single write per line keeps concurrent listeners from interleaving
+        self.file
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn format_record(
+    time: SystemTime,
+    peer: SocketAddr,
+    session_info: &TftpSessionInfo,
+    outcome: &Outcome,
+) -> String {
+    let options = session_info
+        .options
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join(",");
+    let mut line = format!(
+        "time={} peer={} op={} file={} mode={} options={} bytes={} duration_ms={}",
+        timestamp(time),
+        peer,
+        if session_info.upload { "write" } else { "read" },
+        quote(&session_info.file_name),
+        session_info.mode.as_str(),
+        quote(&options),
+        session_info.bytes,
+        session_info.started.elapsed().as_millis()
+    );
+    match outcome {
+        Outcome::Complete => line.push_str(" outcome=complete"),
+        Outcome::TimedOut => line.push_str(" outcome=timeout"),
+        Outcome::Failed {
+            error_code,
+            error_message,
+        }
+        | Outcome::Aborted {
+            error_code,
+            error_message,
+        } => {
+            let kind = match outcome {
+                Outcome::Failed { .. } => "failed",
+                _ => "aborted",
+            };
+            line.push_str(&format!(
+                " outcome={} error_code={} error_message={}",
+                kind,
+                error_code,
+                quote(error_message)
+            ));
+        }
+    }
+    line.push('\n');
+    line
+}
@@ -0,0 +1,349 @@
+use crate::netascii::{NetasciiReader, NetasciiWriter};
+use crate::transfer::{Progress, ProgressCallback, TransferProgress};
+use crate::{
+    build_message, send_queued, ErrorCode, Message, MessageBuf, Mode, TftpError, TftpSessionInfo,
+    DEFAULT_BLOCK_SIZE, DEFAULT_RETRIES, DEFAULT_TIMEOUT, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE,
+};
+use log::debug;
+use std::io::{BufRead, Error, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "tokio")]
+mod asynchronous;
+
+pub struct TftpClientBuilder {
+    // resolved when the builder is created, errors surface from `build`
+    servers: Result<Vec<SocketAddr>, Error>,
+    mode: Mode,
+    block_size: Option<usize>,
+    window_size: Option<u16>,
+    timeout: Duration,
+    retries: u32,
+    progress: Option<ProgressCallback>,
+}
+
+impl TftpClientBuilder {
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    // Block size to request from the server, the default of 512 is used if it
+    // does not support the option.
+    pub fn block_size(mut self, block_size: Option<usize>) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    pub fn window_size(mut self, window_size: Option<u16>) -> Self {
+        self.window_size = window_size;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    // Called as every block goes through, the total is known when the server
+    // answers the tsize option.
+    pub fn progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&TransferProgress) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    pub fn build(self) -> Result<TftpClient, TftpError> {
+        if let Some(block_size) = self.block_size {
+            if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size) {
+                return Err(TftpError::config(format!(
+                    "block size must be between {} and {}",
+                    MIN_BLOCK_SIZE, MAX_BLOCK_SIZE
+                )));
+            }
+        }
+        if self.window_size == Some(0) {
+            return Err(TftpError::config("window size must be at least 1"));
+        }
+        let servers = self.servers?;
+        if servers.is_empty() {
+            return Err(TftpError::config(
+                "server name did not resolve to any address",
+            ));
+        }
+        Ok(TftpClient {
+            servers,
+            mode: self.mode,
+            block_size: self.block_size,
+            window_size: self.window_size,
+            timeout: self.timeout,
+            retries: self.retries,
+            progress: self.progress,
+        })
+    }
+}
+
+pub struct TftpClient {
+    servers: Vec<SocketAddr>,
+    mode: Mode,
+    block_size: Option<usize>,
+    window_size: Option<u16>,
+    timeout: Duration,
+    retries: u32,
+    progress: Option<ProgressCallback>,
+}
+
+impl TftpClient {
+    // Accepts anything that resolves to socket addresses, such as
+    // `"tftp.example.com:69"` or `("::1", 69)`.
+    pub fn builder<A: ToSocketAddrs>(server: A) -> TftpClientBuilder {
+        TftpClientBuilder {
+            servers: server
+                .to_socket_addrs()
+                .map(|addresses| addresses.collect()),
+            mode: Mode::default(),
+            block_size: None,
+            window_size: None,
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+            progress: None,
+        }
+    }
+
+    pub fn servers(&self) -> &[SocketAddr] {
+        &self.servers
+    }
+
+    // Downloads `remote` into `output`, returning the number of bytes received.
+    pub fn get<W: Write + Send + 'static>(
+        &self,
+        remote: &str,
+        output: W,
+    ) -> Result<u64, TftpError> {
+        let (session_info, request) = self.read_session(remote, output);
+        self.transfer(session_info, request)
+    }
+
+    // Uploads `input` as `remote`, returning the number of bytes sent.
+    pub fn put<R: BufRead + Send + 'static>(
+        &self,
+        input: R,
+        remote: &str,
+    ) -> Result<u64, TftpError> {
+        self.put_with_size(input, remote, None)
+    }
+
+    // Uploads like `put`, announcing the size of the input to the server
+    // with the tsize option.
+    pub fn put_with_size<R: BufRead + Send + 'static>(
+        &self,
+        input: R,
+        remote: &str,
+        size: Option<u64>,
+    ) -> Result<u64, TftpError> {
+        let (session_info, request) = self.write_session(input, remote, size);
+        self.transfer(session_info, request)
+    }
+
+    fn read_session<W: Write + Send + 'static>(
+        &self,
+        remote: &str,
+        output: W,
+    ) -> (TftpSessionInfo, MessageBuf) {
+        let mut session_info = self.new_session(remote);
+        session_info.writer = Some(match self.mode {
+            Mode::Netascii => Box::new(NetasciiWriter::new(output)),
+            Mode::Octet => Box::new(output),
+        });
+        let request = MessageBuf::ReadRequest {
+            file_name: remote.as_bytes().to_vec(),
+            mode: self.mode.as_str().to_string(),
+            // asks the server for the size of the file
+            options: self.requested_options(Some(0)),
+        };
+        (session_info, request)
+    }
+
+    fn write_session<R: BufRead + Send + 'static>(
+        &self,
+        input: R,
+        remote: &str,
+        size: Option<u64>,
+    ) -> (TftpSessionInfo, MessageBuf) {
+        // netascii changes the size on the wire, so it is only known for octet
+        let size = size.filter(|_| self.mode == Mode::Octet);
+        let mut session_info = self.new_session(remote);
+        session_info.total = size;
+        session_info.reader = Some(match self.mode {
+            Mode::Netascii => Box::new(NetasciiReader::new(input)),
+            Mode::Octet => Box::new(input) as Box<dyn Read + Send>,
+        });
+        let request = MessageBuf::WriteRequest {
+            file_name: remote.as_bytes().to_vec(),
+            mode: self.mode.as_str().to_string(),
+            options: self.requested_options(size),
+        };
+        (session_info, request)
+    }
+
+    fn new_session(&self, remote: &str) -> TftpSessionInfo {
+        let mut session_info = TftpSessionInfo::new();
+        session_info.file_name = remote.to_string();
+        session_info.timeout = self.timeout;
+        session_info.max_retries = self.retries;
+        session_info.on_progress = self.progress.clone();
+        session_info
+    }
+
+    fn requested_options(&self, size: Option<u64>) -> Vec<(String, String)> {
+        let mut options = Vec::new();
+        if let Some(block_size) = self.block_size {
+            options.push(("blksize".to_string(), block_size.to_string()));
+        }
+        if let Some(window_size) = self.window_size {
+            options.push(("windowsize".to_string(), window_size.to_string()));
+        }
+        if let Some(size) = size {
+            options.push(("tsize".to_string(), size.to_string()));
+        }
+        options
+    }
+
+    fn buffer_size(&self) -> usize {
+        self.block_size.unwrap_or(DEFAULT_BLOCK_SIZE) + 4
+    }
+
+    // Tries each address of the server in turn until one of them answers.
+    fn transfer(
+        &self,
+        mut session_info: TftpSessionInfo,
+        request: MessageBuf,
+    ) -> Result<u64, TftpError> {
+        for server in &self.servers {
+            if self.servers.len() > 1 {
+                debug!(server:% = server; "trying");
+            }
+            let socket = UdpSocket::bind(SocketAddr::new(unspecified(server), 0))?;
+            let result =
+                self.transfer_with(&socket, *server, &mut session_info, request.clone())?;
+            if let Some(bytes) = result {
+                return Ok(bytes);
+            }
+        }
+        Err(no_response())
+    }
+
+    // Runs the transfer against one address, returning `None` if it never
+    // answered the request.
+    fn transfer_with(
+        &self,
+        socket: &UdpSocket,
+        server: SocketAddr,
+        session_info: &mut TftpSessionInfo,
+        request: MessageBuf,
+    ) -> Result<Option<u64>, TftpError> {
+        let mut buffer = vec![0; self.buffer_size()];
+        let mut connection = Connection::new(server);
+        session_info.peer = server;
+        session_info.send_packet(Instant::now(), 0, request)?;
+        send_queued(socket, server, session_info)?;
+        loop {
+            socket.set_read_timeout(Some(wait_time(session_info, self.timeout)))?;
+            match socket.recv_from(&mut buffer) {
+                Ok((amt, source)) => {
+                    if !connection.accepts(source) {
+                        socket.send_to(&unknown_transfer_id()?, source)?;
+                        continue;
+                    }
+                    session_info.peer = connection.peer;
+                    let result = session_info.handle_datagram(Instant::now(), &buffer[..amt]);
+                    send_queued(socket, connection.peer, session_info)?;
+                    if result? == Progress::Complete {
+                        return Ok(Some(session_info.bytes));
+                    }
+                }
+                Err(error)
+                    if error.kind() == ErrorKind::WouldBlock
+                        || error.kind() == ErrorKind::TimedOut => {}
+                Err(error) => return Err(error.into()),
+            }
+            match session_info.handle_timeout(Instant::now()) {
+                Ok(true) => {
+                    send_queued(socket, connection.peer, session_info)?;
+                    debug!(
+                        block = session_info.block_number,
+                        attempt = session_info.retries;
+                        "retransmitting"
+                    );
+                }
+                Ok(false) => {}
+                Err(_) if !connection.connected => return Ok(None),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+// Tracks the transfer ID of the server, which may answer the request from a
+// new port that then identifies the transfer.
+struct Connection {
+    server: SocketAddr,
+    peer: SocketAddr,
+    connected: bool,
+}
+
+impl Connection {
+    fn new(server: SocketAddr) -> Self {
+        Connection {
+            server,
+            peer: server,
+            connected: false,
+        }
+    }
+
+    fn accepts(&mut self, source: SocketAddr) -> bool {
+        if !self.connected && source.ip() == self.server.ip() {
+            self.peer = source;
+            self.connected = true;
+        }
+        source == self.peer
+    }
+}
+
+fn unspecified(server: &SocketAddr) -> IpAddr {
+    match server {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    }
+}
+
+// How long to wait for the next packet before the session wants to
+// retransmit.
+fn wait_time(session_info: &TftpSessionInfo, timeout: Duration) -> Duration {
+    session_info
+        .poll_timeout()
+        .map_or(timeout, |deadline| {
+            deadline.saturating_duration_since(Instant::now())
+        })
+        .max(Duration::from_millis(1))
+}
+
+fn unknown_transfer_id() -> Result<Vec<u8>, TftpError> {
+    build_message(&Message::Error {
+        error_code: ErrorCode::UnknownTransferId,
+        error_message: ErrorCode::UnknownTransferId.message(),
+    })
+}
+
+fn no_response() -> TftpError {
+    TftpError::TimedOut("no response from the server".to_string())
+}
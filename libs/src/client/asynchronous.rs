@@ -0,0 +1,115 @@
+use super::{no_response, unknown_transfer_id, unspecified, wait_time, Connection, TftpClient};
+use crate::transfer::Progress;
+use crate::{MessageBuf, TftpError, TftpSessionInfo};
+use log::debug;
+use std::io::{BufRead, Write};
+use std::net::SocketAddr;
+use std::time::Instant;
+use tokio::net::UdpSocket;
+use tokio::time;
+
+// The same transfers as the blocking methods, with tokio driving the socket.
+// The session still reads and writes the file itself, so blocking readers and
+// writers stall the task while they do.
+impl TftpClient {
+    pub async fn get_async<W: Write + Send + 'static>(
+        &self,
+        remote: &str,
+        output: W,
+    ) -> Result<u64, TftpError> {
+        let (session_info, request) = self.read_session(remote, output);
+        self.transfer_async(session_info, request).await
+    }
+
+    pub async fn put_async<R: BufRead + Send + 'static>(
+        &self,
+        input: R,
+        remote: &str,
+    ) -> Result<u64, TftpError> {
+        self.put_with_size_async(input, remote, None).await
+    }
+
+    pub async fn put_with_size_async<R: BufRead + Send + 'static>(
+        &self,
+        input: R,
+        remote: &str,
+        size: Option<u64>,
+    ) -> Result<u64, TftpError> {
+        let (session_info, request) = self.write_session(input, remote, size);
+        self.transfer_async(session_info, request).await
+    }
+
+    async fn transfer_async(
+        &self,
+        mut session_info: TftpSessionInfo,
+        request: MessageBuf,
+    ) -> Result<u64, TftpError> {
+        for server in &self.servers {
+            if self.servers.len() > 1 {
+                debug!(server:% = server; "trying");
+            }
+            let socket = UdpSocket::bind(SocketAddr::new(unspecified(server), 0)).await?;
+            let result = self
+                .transfer_with_async(&socket, *server, &mut session_info, request.clone())
+                .await?;
+            if let Some(bytes) = result {
+                return Ok(bytes);
+            }
+        }
+        Err(no_response())
+    }
+
+    async fn transfer_with_async(
+        &self,
+        socket: &UdpSocket,
+        server: SocketAddr,
+        session_info: &mut TftpSessionInfo,
+        request: MessageBuf,
+    ) -> Result<Option<u64>, TftpError> {
+        let mut buffer = vec![0; self.buffer_size()];
+        let mut connection = Connection::new(server);
+        session_info.peer = server;
+        session_info.send_packet(Instant::now(), 0, request)?;
+        send_queued(socket, server, session_info).await?;
+        loop {
+            let wait = wait_time(session_info, self.timeout);
+            if let Ok(received) = time::timeout(wait, socket.recv_from(&mut buffer)).await {
+                let (amt, source) = received?;
+                if !connection.accepts(source) {
+                    socket.send_to(&unknown_transfer_id()?, source).await?;
+                    continue;
+                }
+                session_info.peer = connection.peer;
+                let result = session_info.handle_datagram(Instant::now(), &buffer[..amt]);
+                send_queued(socket, connection.peer, session_info).await?;
+                if result? == Progress::Complete {
+                    return Ok(Some(session_info.bytes));
+                }
+            }
+            match session_info.handle_timeout(Instant::now()) {
+                Ok(true) => {
+                    send_queued(socket, connection.peer, session_info).await?;
+                    debug!(
+                        block = session_info.block_number,
+                        attempt = session_info.retries;
+                        "retransmitting"
+                    );
+                }
+                Ok(false) => {}
+                Err(_) if !connection.connected => return Ok(None),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+async fn send_queued(
+    socket: &UdpSocket,
+    destination: SocketAddr,
+    session_info: &mut TftpSessionInfo,
+) -> Result<(), TftpError> {
+    while let Some(packet) = session_info.poll_transmit() {
+        socket.send_to(packet, destination).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,356 @@
+use crate::acl::{Acl, AclAction, AclOperation, AclRule, Cidr};
+use crate::server::ServerMode;
+use crate::{FileNameEncoding, TftpError, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE};
+use serde::Deserialize;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use toml::Spanned;
+
+pub const DEFAULT_PORT: u16 = 69;
+
+// Server settings read from a TOML file. Anything left out of the file is
+// `None` so that command-line flags and builder defaults can fill it in.
+//
+// ```toml
+// root = "/srv/tftp"
+// mode = "read-only"          # read-write, read-only or write-only
+// create = false
+// filename_encoding = "utf-8" # ascii, utf-8 or lossy
+//
+// [[listener]]
+// address = "0.0.0.0:69"
+// root = "/srv/tftp/pxe"      # optional, defaults to the top level root
+//
+// [[acl]]
+// action = "allow"            # allow or deny
+// operation = "read"          # read, write or any
+// network = "10.0.0.0/8"
+// path = "pxe/*"              # relative to the root, no leading slash
+//
+// [options]
+// max_blksize = 1468
+// max_windowsize = 64
+//
+// [timeouts]
+// timeout = 5                 # seconds
+// retries = 5
+// shutdown = 30               # seconds to let transfers finish on shutdown
+// idle = 60                   # exit after this many seconds without requests
+// session = 60                # drop a transfer the peer has not advanced for this long
+//
+// [logging]
+// verbosity = 1
+// file = "/var/log/tftp/server.log"   # instead of standard error
+// audit = "/var/log/tftp/audit.log"   # one line per finished transfer
+//
+// [metrics]                   # needs the metrics cargo feature
+// address = "127.0.0.1:9069"  # serve GET /metrics on this address
+//
+// [privileges]                # applied by the server binary once bound
+// user = "tftp"
+// group = "tftp"              # defaults to the primary group of the user
+// chroot = true               # confine the server to the root directory
+// allow_root = false          # permit running as root when no user is set
+// ```
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub listeners: Vec<ListenerConfig>,
+    pub root: Option<PathBuf>,
+    pub mode: Option<ServerMode>,
+    pub file_name_encoding: Option<FileNameEncoding>,
+    pub create: Option<bool>,
+    pub acl: Option<Acl>,
+    pub max_block_size: Option<usize>,
+    pub max_window_size: Option<u16>,
+    pub timeout: Option<Duration>,
+    pub retries: Option<u32>,
+    pub shutdown_timeout: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+    pub session_timeout: Option<Duration>,
+    pub verbosity: Option<u8>,
+    // applied by the server binary, which owns the logger
+    pub log_file: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    pub metrics_address: Option<SocketAddr>,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub chroot: Option<bool>,
+    pub allow_root: Option<bool>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ListenerConfig {
+    pub address: SocketAddr,
+    pub root: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    root: Option<Spanned<PathBuf>>,
+    mode: Option<Spanned<String>>,
+    create: Option<bool>,
+    filename_encoding: Option<Spanned<String>>,
+    #[serde(default)]
+    listener: Vec<RawListener>,
+    acl: Option<Vec<RawAclRule>>,
+    #[serde(default)]
+    options: RawOptions,
+    #[serde(default)]
+    timeouts: RawTimeouts,
+    #[serde(default)]
+    logging: RawLogging,
+    #[serde(default)]
+    metrics: RawMetrics,
+    #[serde(default)]
+    privileges: RawPrivileges,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawListener {
+    address: Spanned<String>,
+    root: Option<Spanned<PathBuf>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawAclRule {
+    action: Spanned<String>,
+    operation: Option<Spanned<String>>,
+    network: Spanned<String>,
+    path: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawOptions {
+    max_blksize: Option<Spanned<usize>>,
+    max_windowsize: Option<Spanned<u16>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawTimeouts {
+    timeout: Option<Spanned<u64>>,
+    retries: Option<u32>,
+    shutdown: Option<u64>,
+    idle: Option<u64>,
+    session: Option<Spanned<u64>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawLogging {
+    verbosity: Option<u8>,
+    file: Option<PathBuf>,
+    audit: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawMetrics {
+    address: Option<Spanned<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawPrivileges {
+    user: Option<String>,
+    group: Option<String>,
+    chroot: Option<bool>,
+    allow_root: Option<bool>,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(file_name: P) -> Result<Self, TftpError> {
+        let file_name = file_name.as_ref();
+        let contents = fs::read_to_string(file_name)
+            .map_err(|error| TftpError::from(error).context(file_name.display()))?;
+        Config::parse(&contents).map_err(|error| error.context(file_name.display()))
+    }
+
+    // Parses and validates a configuration, every error names the offending line.
+    pub fn parse(contents: &str) -> Result<Self, TftpError> {
+        let raw: RawConfig =
+            toml::from_str(contents).map_err(|error| TftpError::config(error.to_string()))?;
+        let at = |span: std::ops::Range<usize>, message: String| {
+            let line = contents[..span.start].matches('\n').count() + 1;
+            TftpError::config(format!("line {}: {}", line, message))
+        };
+
+        let root = match raw.root {
+            Some(root) => Some(check_root(root, &at)?),
+            None => None,
+        };
+        let mode = match raw.mode {
+            Some(mode) => Some(match mode.get_ref().as_str() {
+                "read-write" => ServerMode::ReadWrite,
+                "read-only" => ServerMode::ReadOnly,
+                "write-only" => ServerMode::WriteOnly,
+                other => return Err(at(mode.span(), format!("unknown mode {}", other))),
+            }),
+            None => None,
+        };
+        let file_name_encoding = match raw.filename_encoding {
+            Some(encoding) => {
+                Some(FileNameEncoding::parse(encoding.get_ref()).ok_or_else(|| {
+                    at(
+                        encoding.span(),
+                        format!("unknown filename encoding {}", encoding.get_ref()),
+                    )
+                })?)
+            }
+            None => None,
+        };
+
+        let mut listeners = Vec::new();
+        for listener in raw.listener {
+            let address =
+                parse_address(listener.address.get_ref(), DEFAULT_PORT).ok_or_else(|| {
+                    at(
+                        listener.address.span(),
+                        format!("invalid address {}", listener.address.get_ref()),
+                    )
+                })?;
+            let root = match listener.root {
+                Some(root) => Some(check_root(root, &at)?),
+                None => None,
+            };
+            listeners.push(ListenerConfig { address, root });
+        }
+
+        let acl = match raw.acl {
+            Some(rules) => {
+                let mut acl = Acl::new();
+                for rule in rules {
+                    let action = rule
+                        .action
+                        .get_ref()
+                        .parse::<AclAction>()
+                        .map_err(|error| at(rule.action.span(), error.to_string()))?;
+                    let operation = match &rule.operation {
+                        Some(operation) => operation
+                            .get_ref()
+                            .parse::<AclOperation>()
+                            .map_err(|error| at(operation.span(), error.to_string()))?,
+                        None => AclOperation::Any,
+                    };
+                    let network = Cidr::parse(rule.network.get_ref())
+                        .map_err(|error| at(rule.network.span(), error.to_string()))?;
+                    acl.add_rule(AclRule {
+                        action,
+                        operation,
+                        network,
+                        path_glob: rule.path.unwrap_or_else(|| "*".to_string()),
+                    });
+                }
+                Some(acl)
+            }
+            None => None,
+        };
+
+        let max_block_size = match raw.options.max_blksize {
+            Some(size) if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(size.get_ref()) => {
+                return Err(at(
+                    size.span(),
+                    format!(
+                        "max_blksize must be between {} and {}",
+                        MIN_BLOCK_SIZE, MAX_BLOCK_SIZE
+                    ),
+                ));
+            }
+            size => size.map(Spanned::into_inner),
+        };
+        let max_window_size = match raw.options.max_windowsize {
+            Some(size) if *size.get_ref() == 0 => {
+                return Err(at(
+                    size.span(),
+                    "max_windowsize must be at least 1".to_string(),
+                ));
+            }
+            size => size.map(Spanned::into_inner),
+        };
+        let timeout = match raw.timeouts.timeout {
+            Some(timeout) if !(1..=255).contains(timeout.get_ref()) => {
+                return Err(at(
+                    timeout.span(),
+                    "timeout must be between 1 and 255 seconds".to_string(),
+                ));
+            }
+            timeout => timeout.map(|timeout| Duration::from_secs(timeout.into_inner())),
+        };
+
+        let metrics_address = match raw.metrics.address {
+            Some(address) => Some(address.get_ref().parse::<SocketAddr>().map_err(|_| {
+                at(
+                    address.span(),
+                    format!("invalid metrics address {}", address.get_ref()),
+                )
+            })?),
+            None => None,
+        };
+
+        let session_timeout = match raw.timeouts.session {
+            Some(session) if *session.get_ref() == 0 => {
+                return Err(at(
+                    session.span(),
+                    "session timeout must be at least 1 second".to_string(),
+                ));
+            }
+            session => session.map(|session| Duration::from_secs(session.into_inner())),
+        };
+
+        Ok(Config {
+            listeners,
+            root,
+            mode,
+            file_name_encoding,
+            create: raw.create,
+            acl,
+            max_block_size,
+            max_window_size,
+            timeout,
+            retries: raw.timeouts.retries,
+            shutdown_timeout: raw.timeouts.shutdown.map(Duration::from_secs),
+            idle_timeout: raw.timeouts.idle.map(Duration::from_secs),
+            session_timeout,
+            verbosity: raw.logging.verbosity,
+            log_file: raw.logging.file,
+            audit_log: raw.logging.audit,
+            metrics_address,
+            user: raw.privileges.user,
+            group: raw.privileges.group,
+            chroot: raw.privileges.chroot,
+            allow_root: raw.privileges.allow_root,
+        })
+    }
+}
+
+fn check_root<F>(root: Spanned<PathBuf>, at: &F) -> Result<PathBuf, TftpError>
+where
+    F: Fn(std::ops::Range<usize>, String) -> TftpError,
+{
+    if !root.get_ref().is_dir() {
+        return Err(at(
+            root.span(),
+            format!("root {} is not a directory", root.get_ref().display()),
+        ));
+    }
+    Ok(root.into_inner())
+}
+
+// Accepts `1.2.3.4:69`, `[::1]:69` or an address without a port, with or
+// without brackets around IPv6, which listens on `port`.
+pub fn parse_address(address: &str, port: u16) -> Option<SocketAddr> {
+    address.parse::<SocketAddr>().ok().or_else(|| {
+        address
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .ok()
+            .map(|ip| SocketAddr::new(ip, port))
+    })
+}
@@ -0,0 +1,190 @@
+use std::fmt;
+use std::io;
+
+// Everything that can go wrong in the library, from the sockets and files up
+// to the peer giving up on a transfer.
+#[derive(Debug)]
+pub enum TftpError {
+    Io(io::Error),
+    // a packet that is malformed or not allowed at this point of the transfer
+    Protocol(String),
+    // an error packet received from the peer
+    Remote {
+        error_code: ErrorCode,
+        error_message: String,
+    },
+    // the peer stopped answering
+    TimedOut(String),
+    // invalid settings, configuration files or access control lists
+    Config(String),
+}
+
+impl TftpError {
+    pub fn protocol<M: Into<String>>(message: M) -> Self {
+        TftpError::Protocol(message.into())
+    }
+
+    pub fn config<M: Into<String>>(message: M) -> Self {
+        TftpError::Config(message.into())
+    }
+
+    // Adds what was being done to the message, such as the file involved.
+    pub fn context<C: fmt::Display>(self, context: C) -> Self {
+        match self {
+            TftpError::Io(error) => TftpError::Io(io::Error::new(
+                error.kind(),
+                format!("{}: {}", context, error),
+            )),
+            TftpError::Protocol(message) => {
+                TftpError::Protocol(format!("{}: {}", context, message))
+            }
+            TftpError::TimedOut(message) => {
+                TftpError::TimedOut(format!("{}: {}", context, message))
+            }
+            TftpError::Config(message) => TftpError::Config(format!("{}: {}", context, message)),
+            remote @ TftpError::Remote { .. } => remote,
+        }
+    }
+}
+
+impl fmt::Display for TftpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TftpError::Io(error) => write!(f, "{}", error),
+            TftpError::Protocol(message)
+            | TftpError::TimedOut(message)
+            | TftpError::Config(message) => write!(f, "{}", message),
+            TftpError::Remote {
+                error_code,
+                error_message,
+            } => write!(f, "error {}: {}", u16::from(*error_code), error_message),
+        }
+    }
+}
+
+impl std::error::Error for TftpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TftpError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TftpError {
+    fn from(error: io::Error) -> Self {
+        TftpError::Io(error)
+    }
+}
+
+// For callers that only deal in `io::Error`, the kind keeps timeouts and
+// protocol violations apart.
+impl From<TftpError> for io::Error {
+    fn from(error: TftpError) -> Self {
+        match error {
+            TftpError::Io(error) => error,
+            TftpError::Protocol(_) => io::Error::new(io::ErrorKind::InvalidData, error),
+            TftpError::TimedOut(_) => io::Error::new(io::ErrorKind::TimedOut, error),
+            TftpError::Config(_) => io::Error::new(io::ErrorKind::InvalidInput, error),
+            TftpError::Remote { .. } => io::Error::other(error),
+        }
+    }
+}
+
+// The codes of RFC 1350 and the option negotiation code of RFC 2347. Codes
+// outside of them are passed through as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrorCode {
+    NotDefined,
+    FileNotFound,
+    AccessViolation,
+    DiskFull,
+    IllegalOperation,
+    UnknownTransferId,
+    FileAlreadyExists,
+    NoSuchUser,
+    OptionNegotiation,
+    Unknown(u16),
+}
+
+impl ErrorCode {
+    // The message sent along with the code when there is nothing more
+    // specific to say.
+    pub fn message(&self) -> &'static str {
+        match self {
+            ErrorCode::NotDefined | ErrorCode::Unknown(_) => "Not defined",
+            ErrorCode::FileNotFound => "File not found",
+            ErrorCode::AccessViolation => "Access violation",
+            ErrorCode::DiskFull => "Disk full or allocation exceeded",
+            ErrorCode::IllegalOperation => "Illegal TFTP operation",
+            ErrorCode::UnknownTransferId => "Unknown transfer ID",
+            ErrorCode::FileAlreadyExists => "File already exists",
+            ErrorCode::NoSuchUser => "No such user",
+            ErrorCode::OptionNegotiation => "Option negotiation failed",
+        }
+    }
+}
+
+impl From<u16> for ErrorCode {
+    fn from(code: u16) -> Self {
+        match code {
+            0 => ErrorCode::NotDefined,
+            1 => ErrorCode::FileNotFound,
+            2 => ErrorCode::AccessViolation,
+            3 => ErrorCode::DiskFull,
+            4 => ErrorCode::IllegalOperation,
+            5 => ErrorCode::UnknownTransferId,
+            6 => ErrorCode::FileAlreadyExists,
+            7 => ErrorCode::NoSuchUser,
+            8 => ErrorCode::OptionNegotiation,
+            code => ErrorCode::Unknown(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for u16 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::NotDefined => 0,
+            ErrorCode::FileNotFound => 1,
+            ErrorCode::AccessViolation => 2,
+            ErrorCode::DiskFull => 3,
+            ErrorCode::IllegalOperation => 4,
+            ErrorCode::UnknownTransferId => 5,
+            ErrorCode::FileAlreadyExists => 6,
+            ErrorCode::NoSuchUser => 7,
+            ErrorCode::OptionNegotiation => 8,
+            ErrorCode::Unknown(code) => code,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", u16::from(*self))
+    }
+}
+
+impl From<&io::Error> for ErrorCode {
+    fn from(error: &io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::NotFound => ErrorCode::FileNotFound,
+            io::ErrorKind::PermissionDenied => ErrorCode::AccessViolation,
+            io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => ErrorCode::DiskFull,
+            io::ErrorKind::Unsupported => ErrorCode::IllegalOperation,
+            io::ErrorKind::AlreadyExists => ErrorCode::FileAlreadyExists,
+            _ => ErrorCode::NotDefined,
+        }
+    }
+}
+
+impl From<&TftpError> for ErrorCode {
+    fn from(error: &TftpError) -> Self {
+        match error {
+            TftpError::Io(error) => ErrorCode::from(error),
+            TftpError::Protocol(_) => ErrorCode::IllegalOperation,
+            TftpError::Remote { error_code, .. } => *error_code,
+            TftpError::TimedOut(_) | TftpError::Config(_) => ErrorCode::NotDefined,
+        }
+    }
+}
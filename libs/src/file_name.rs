@@ -0,0 +1,83 @@
+use crate::TftpError;
+use std::fmt;
+
+// A file name as it travels in a request, the bytes up to the NUL terminator.
+// The protocol gives them no encoding, the server picks one to decode them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct FileName<'t>(&'t [u8]);
+
+impl<'t> FileName<'t> {
+    pub fn new(bytes: &'t [u8]) -> Self {
+        FileName(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &'t [u8] {
+        self.0
+    }
+
+    pub fn decode(&self, encoding: FileNameEncoding) -> Result<String, TftpError> {
+        match encoding {
+            FileNameEncoding::Ascii if !self.0.is_ascii() => Err(TftpError::protocol(format!(
+                "file name {} is not ASCII",
+                self
+            ))),
+            FileNameEncoding::Ascii | FileNameEncoding::Utf8 => std::str::from_utf8(self.0)
+                .map(str::to_string)
+                .map_err(|_| TftpError::protocol(format!("file name {} is not valid UTF-8", self))),
+            FileNameEncoding::Lossy => Ok(String::from_utf8_lossy(self.0).into_owned()),
+        }
+    }
+}
+
+impl<'t> From<&'t str> for FileName<'t> {
+    fn from(file_name: &'t str) -> Self {
+        FileName(file_name.as_bytes())
+    }
+}
+
+// Safe to log whatever the peer sent: invalid UTF-8 shows as U+FFFD and
+// control characters are escaped.
+impl fmt::Display for FileName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in String::from_utf8_lossy(self.0).chars() {
+            if c.is_control() {
+                write!(f, "{}", c.escape_debug())?;
+            } else {
+                write!(f, "{}", c)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// How the server turns requested file names into strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileNameEncoding {
+    // refuse anything but ASCII, which is all RFC 1350 allows
+    Ascii,
+    // refuse invalid UTF-8
+    #[default]
+    Utf8,
+    // replace invalid UTF-8 with U+FFFD, so names in a legacy encoding
+    // cannot be told apart
+    Lossy,
+}
+
+impl FileNameEncoding {
+    pub fn parse(encoding: &str) -> Option<FileNameEncoding> {
+        match encoding.to_lowercase().as_str() {
+            "ascii" => Some(FileNameEncoding::Ascii),
+            "utf-8" | "utf8" => Some(FileNameEncoding::Utf8),
+            "lossy" => Some(FileNameEncoding::Lossy),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileNameEncoding::Ascii => "ascii",
+            FileNameEncoding::Utf8 => "utf-8",
+            FileNameEncoding::Lossy => "lossy",
+        }
+    }
+}
@@ -1,231 +1,686 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Error, ErrorKind};
+use std::io::{BufReader, ErrorKind, Read, Write};
 use std::net::{SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use transfer::{ProgressCallback, SessionState};
 
-fn build_message(tftp_message: Message) -> Vec<u8> {
-    match tftp_message {
-        Message::ReadRequest { file_name, mode } => {
-            let mut message = vec![0; 2 + file_name.len() + 1 + mode.len() + 1];
-            message[0] = 0;
-            message[1] = OpCode::Read as u8;
-            message[2..file_name.len() + 2].copy_from_slice(file_name.as_bytes());
-            message[file_name.len() + 2] = 0;
-            message[file_name.len() + 2 + 1..file_name.len() + 2 + 1 + mode.len()]
-                .copy_from_slice(mode.as_bytes());
-            message[file_name.len() + 2 + 1 + mode.len()] = 0;
-            message
-        }
-        Message::WriteRequest { file_name, mode } => {
-            let mut message = vec![0; 2 + file_name.len() + 1 + mode.len() + 1];
-            message[0] = 0;
-            message[1] = OpCode::Write as u8;
-            message[2..file_name.len() + 2].copy_from_slice(file_name.as_bytes());
-            message[file_name.len() + 2] = 0;
-            message[file_name.len() + 1 + 2..file_name.len() + 1 + 2 + mode.len()]
-                .copy_from_slice(mode.as_bytes());
-            message[file_name.len() + 1 + 2 + mode.len()] = 0;
-            message
-        }
-        Message::Data {
-            block_number,
-            data,
-            length,
-        } => {
-            let mut message = vec![0; 4 + length];
-            //TODO verify block length is not greater than 512
-            message[0] = 0;
-            message[1] = OpCode::Data as u8;
-            message[2] = (block_number >> 8) as u8;
-            message[3] = block_number as u8;
-            message[4..4 + length].copy_from_slice(data);
-            message
-        }
-        Message::Ack { block_number } => {
-            let mut message = vec![0; 4];
-            message[0] = 0;
-            message[1] = OpCode::Ack as u8;
-            message[2] = (block_number >> 8) as u8;
-            message[3] = block_number as u8;
-            message
-        }
-        Message::Error {
-            error_code,
-            error_message,
-        } => {
-            let mut message = vec![0; 4 + error_message.len() + 1];
-            message[0] = 0;
-            message[1] = OpCode::Error as u8;
-            message[2] = (error_code >> 8) as u8;
-            message[3] = error_code as u8;
-            message[4..error_message.len() + 4].copy_from_slice(error_message.as_bytes());
-            message[4 + error_message.len()] = 0;
-            message
-        }
-    }
+pub mod acl;
+pub mod audit;
+pub mod client;
+pub mod config;
+pub mod error;
+pub mod file_name;
+pub mod logging;
+pub mod metrics;
+pub mod netascii;
+pub mod server;
+pub mod transfer;
+
+pub use error::{ErrorCode, TftpError};
+pub use file_name::{FileName, FileNameEncoding};
+
+pub const DEFAULT_BLOCK_SIZE: usize = 512;
+pub const MIN_BLOCK_SIZE: usize = 8;
+pub const MAX_BLOCK_SIZE: usize = 65464;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEFAULT_RETRIES: u32 = 5;
+pub const DEFAULT_WINDOW_SIZE: u16 = 1;
+// Largest size the tsize option may announce, 8 PiB. Past 2^53 bytes the
+// progress figures lose precision, and no real transfer comes near it.
+pub const MAX_TRANSFER_SIZE: u64 = 1 << 53;
+
+// Encodes a packet into a buffer of its own.
+pub fn build_message(message: &Message) -> Result<Vec<u8>, TftpError> {
+    let mut packet = vec![0; message.encoded_len()];
+    message.encode_into(&mut packet)?;
+    Ok(packet)
 }
 
-pub fn extract_opcode(buffer: &[u8]) -> OpCode {
-    //TODO: verify buffer length is at least 2 or exactly 2
-    let opcode = (buffer[0] as u16) << 8 | buffer[1] as u16;
-    match opcode {
-        1 => OpCode::Read,
-        2 => OpCode::Write,
-        3 => OpCode::Data,
-        4 => OpCode::Ack,
-        _ => OpCode::Error,
+pub fn extract_opcode(buffer: &[u8]) -> Result<OpCode, TftpError> {
+    match read_u16(buffer, 0)? {
+        1 => Ok(OpCode::Read),
+        2 => Ok(OpCode::Write),
+        3 => Ok(OpCode::Data),
+        4 => Ok(OpCode::Ack),
+        5 => Ok(OpCode::Error),
+        6 => Ok(OpCode::OptionAck),
+        opcode => Err(TftpError::protocol(format!("unknown opcode {}", opcode))),
     }
 }
 
-pub fn send_tftp_message(udp_socket: &UdpSocket, message: Message, destination: &str) {
-    let message_data = build_message(message);
-    udp_socket
-        .send_to(&message_data, destination)
-        .expect("Failed to send data");
+pub fn send_tftp_message(
+    udp_socket: &UdpSocket,
+    message: Message,
+    destination: SocketAddr,
+) -> Result<(), TftpError> {
+    let message_data = build_message(&message)?;
+    udp_socket.send_to(&message_data, destination)?;
+    Ok(())
+}
+
+// Sends every packet a session has queued for its peer.
+pub fn send_queued(
+    udp_socket: &UdpSocket,
+    destination: SocketAddr,
+    session_info: &mut TftpSessionInfo,
+) -> Result<(), TftpError> {
+    while let Some(packet) = session_info.poll_transmit() {
+        udp_socket.send_to(packet, destination)?;
+    }
+    Ok(())
 }
 
-pub fn extract_message(buffer: &[u8]) -> Message {
-    let opcode = extract_opcode(buffer);
-    match opcode {
+// Parses a packet, anything truncated or with an unknown opcode is a
+// protocol error.
+pub fn extract_message(buffer: &[u8]) -> Result<Message<'_>, TftpError> {
+    let message = match extract_opcode(buffer)? {
         OpCode::Read => {
-            let mut file_name = String::new();
-            let mut mode = String::new();
             let mut i = 2;
-            while buffer[i] != 0 {
-                file_name.push(buffer[i] as char);
-                i += 1;
+            let file_name = FileName::new(read_bytes(buffer, &mut i));
+            let mode = read_string(buffer, &mut i);
+            let options = read_options(buffer, &mut i);
+            Message::ReadRequest {
+                file_name,
+                mode,
+                options,
             }
-            i += 1;
-            while buffer[i] != 0 {
-                mode.push(buffer[i] as char);
-                i += 1;
-            }
-            Message::ReadRequest { file_name, mode }
         }
         OpCode::Write => {
-            let mut file_name = String::new();
-            let mut mode = String::new();
             let mut i = 2;
-            while buffer[i] != 0 {
-                file_name.push(buffer[i] as char);
-                i += 1;
-            }
-            i += 1;
-            while buffer[i] != 0 {
-                mode.push(buffer[i] as char);
-                i += 1;
+            let file_name = FileName::new(read_bytes(buffer, &mut i));
+            let mode = read_string(buffer, &mut i);
+            let options = read_options(buffer, &mut i);
+            Message::WriteRequest {
+                file_name,
+                mode,
+                options,
             }
-            Message::WriteRequest { file_name, mode }
         }
         OpCode::Data => {
-            let block_number = (buffer[2] as u16) << 8 | buffer[3] as u16;
+            let block_number = read_u16(buffer, 2)?;
             let data = &buffer[4..];
-            Message::Data {
-                block_number,
-                data,
-                length: data.len(),
-            }
-        }
-        OpCode::Ack => {
-            let block_number = (buffer[2] as u16) << 8 | buffer[3] as u16;
-            Message::Ack { block_number }
+            Message::Data { block_number, data }
         }
+        OpCode::Ack => Message::Ack {
+            block_number: read_u16(buffer, 2)?,
+        },
         OpCode::Error => {
-            let error_code = (buffer[2] as u16) << 8 | buffer[3] as u16;
-            let mut error_message = String::new();
-            let mut i = 4;
-            while buffer[i] != 0 {
-                error_message.push(buffer[i] as char);
-                i += 1;
-            }
+            let error_code = ErrorCode::from(read_u16(buffer, 2)?);
+            let error_message = read_string(buffer, &mut 4);
             Message::Error {
                 error_code,
                 error_message,
             }
         }
+        OpCode::OptionAck => {
+            let mut i = 2;
+            let options = read_options(buffer, &mut i);
+            Message::OptionAck { options }
+        }
+    };
+    Ok(message)
+}
+
+// Reads the big endian number at `i`, which every fixed size field is.
+fn read_u16(buffer: &[u8], i: usize) -> Result<u16, TftpError> {
+    match buffer.get(i..i + 2) {
+        Some(&[high, low]) => Ok((high as u16) << 8 | low as u16),
+        _ => Err(TftpError::protocol(format!(
+            "truncated packet of {} bytes",
+            buffer.len()
+        ))),
+    }
+}
+
+// Reads a NUL terminated field starting at `i`, leaving `i` after the terminator.
+fn read_bytes<'t>(buffer: &'t [u8], i: &mut usize) -> &'t [u8] {
+    let start = (*i).min(buffer.len());
+    let length = buffer[start..]
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(buffer.len() - start);
+    *i = start + length + 1;
+    &buffer[start..start + length]
+}
+
+// Modes, options and error messages are meant to be ASCII, anything else is
+// decoded as UTF-8 as far as it goes.
+fn read_string<'t>(buffer: &'t [u8], i: &mut usize) -> &'t str {
+    let bytes = read_bytes(buffer, i);
+    match std::str::from_utf8(bytes) {
+        Ok(string) => string,
+        Err(error) => std::str::from_utf8(&bytes[..error.valid_up_to()]).unwrap_or_default(),
     }
 }
 
-pub fn get_read_file_info(file_name: String) -> Result<(BufReader<File>, u64), Error> {
-    match File::open(file_name) {
-        Ok(file) => {
-            let file_length = file.metadata().expect("Unable to read metadata").len();
-            let reader = BufReader::with_capacity(512, file);
-            Ok((reader, file_length))
+// Option names are case insensitive, they are left as sent for whoever
+// negotiates them to compare.
+fn read_options<'t>(buffer: &'t [u8], i: &mut usize) -> Vec<(&'t str, &'t str)> {
+    let mut options = Vec::new();
+    while *i < buffer.len() {
+        let name = read_string(buffer, i);
+        let value = read_string(buffer, i);
+        options.push((name, value));
+    }
+    options
+}
+
+pub fn get_read_file_info<P: AsRef<Path>>(
+    file_name: P,
+) -> Result<(BufReader<File>, u64), TftpError> {
+    let file = File::open(file_name)?;
+    let file_length = file.metadata()?.len();
+    Ok((
+        BufReader::with_capacity(DEFAULT_BLOCK_SIZE, file),
+        file_length,
+    ))
+}
+
+// Reads up to `block_size` bytes, only returning a short block at the end of the file.
+pub fn read_block<R: Read>(reader: &mut R, block_size: usize) -> Result<Vec<u8>, TftpError> {
+    let mut block = Vec::new();
+    read_block_into(reader, &mut block, block_size)?;
+    Ok(block)
+}
+
+// Reads a block like `read_block`, reusing `block` and its allocation.
+pub fn read_block_into<R: Read>(
+    reader: &mut R,
+    block: &mut Vec<u8>,
+    block_size: usize,
+) -> Result<(), TftpError> {
+    block.resize(block_size, 0);
+    let mut length = 0;
+    while length < block_size {
+        match reader.read(&mut block[length..]) {
+            Ok(0) => break,
+            Ok(read) => length += read,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error.into()),
         }
-        Err(error) => Err(error),
     }
+    block.truncate(length);
+    Ok(())
 }
 
-pub fn send_error_message(error: Error, udp_socket: &UdpSocket, destination: &str) {
-    let message: Message = match error.kind() {
-        ErrorKind::NotFound => {
-            // send a not found message
-            Message::Error {
-                error_code: 1,
-                error_message: "File not found".to_string(),
-            }
+pub fn send_error_message(
+    error: &TftpError,
+    udp_socket: &UdpSocket,
+    destination: SocketAddr,
+) -> Result<(), TftpError> {
+    let (error_code, error_message) = error_code(error);
+    send_tftp_message(
+        udp_socket,
+        Message::Error {
+            error_code,
+            error_message: &error_message,
+        },
+        destination,
+    )
+}
+
+// Maps an error onto the code and message of the error packet reported
+// to the peer. Local I/O errors get the standard message of their code, the
+// details stay in the logs.
+pub fn error_code(error: &TftpError) -> (ErrorCode, String) {
+    let error_code = ErrorCode::from(error);
+    let error_message = match error {
+        TftpError::Io(_) if error_code != ErrorCode::NotDefined => error_code.message().to_string(),
+        TftpError::Remote { error_message, .. } => error_message.clone(),
+        error => error.to_string(),
+    };
+    (error_code, error_message)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    Netascii,
+    #[default]
+    Octet,
+}
+
+impl Mode {
+    pub fn parse(mode: &str) -> Option<Mode> {
+        match mode.to_lowercase().as_str() {
+            "netascii" => Some(Mode::Netascii),
+            "octet" => Some(Mode::Octet),
+            _ => None,
         }
-        // send back generic error
-        error => {
-            Message::Error {
-                error_code: 0, //unknown
-                error_message: error.to_string(),
-            }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Netascii => "netascii",
+            Mode::Octet => "octet",
         }
-    };
-    send_tftp_message(udp_socket, message, destination);
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OpCode {
     Read = 1,
     Write = 2,
     Data = 3,
     Ack = 4,
     Error = 5,
+    OptionAck = 6,
 }
 
+// A packet as parsed from a datagram, every field of which stays in the
+// datagram. Only the list of options is allocated, when there are any.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Message<'t> {
     ReadRequest {
-        file_name: String,
-        mode: String,
-    }, //name and mode
+        file_name: FileName<'t>,
+        mode: &'t str,
+        options: Vec<(&'t str, &'t str)>,
+    }, //name, mode and requested options
     WriteRequest {
-        file_name: String,
-        mode: String,
-    }, //name and mode
+        file_name: FileName<'t>,
+        mode: &'t str,
+        options: Vec<(&'t str, &'t str)>,
+    }, //name, mode and requested options
     Data {
         block_number: u16,
         data: &'t [u8],
-        length: usize,
-    }, //block number
+    }, //block number and contents
     Ack {
         block_number: u16,
     }, //block number
     Error {
-        error_code: u16,
-        error_message: String,
+        error_code: ErrorCode,
+        error_message: &'t str,
     }, //error code and error message
+    OptionAck {
+        options: Vec<(&'t str, &'t str)>,
+    }, //accepted options
+}
+
+// The owned counterpart of `Message`, for packets kept around until they are
+// sent or sent again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageBuf {
+    ReadRequest {
+        file_name: Vec<u8>,
+        mode: String,
+        options: Vec<(String, String)>,
+    },
+    WriteRequest {
+        file_name: Vec<u8>,
+        mode: String,
+        options: Vec<(String, String)>,
+    },
+    Data {
+        block_number: u16,
+        data: Vec<u8>,
+    },
+    Ack {
+        block_number: u16,
+    },
+    Error {
+        error_code: ErrorCode,
+        error_message: String,
+    },
+    OptionAck {
+        options: Vec<(String, String)>,
+    },
+}
+
+impl Message<'_> {
+    // Size of the packet on the wire.
+    pub fn encoded_len(&self) -> usize {
+        self.fields().encoded_len()
+    }
+
+    // Strings end at a NUL byte on the wire, so none of them may contain one.
+    pub fn validate(&self) -> Result<(), TftpError> {
+        self.fields().validate()
+    }
+
+    // Writes the packet at the start of `buffer`, returning its length.
+    // Nothing is written if the packet is invalid or does not fit.
+    pub fn encode_into(&self, buffer: &mut [u8]) -> Result<usize, TftpError> {
+        self.fields().encode_into(buffer)
+    }
+
+    fn fields(&self) -> Fields<'_, &str> {
+        match self {
+            Message::ReadRequest {
+                file_name,
+                mode,
+                options,
+            } => Fields::Request(OpCode::Read, file_name.as_bytes(), mode, options),
+            Message::WriteRequest {
+                file_name,
+                mode,
+                options,
+            } => Fields::Request(OpCode::Write, file_name.as_bytes(), mode, options),
+            Message::Data { block_number, data } => Fields::Data(*block_number, data),
+            Message::Ack { block_number } => Fields::Ack(*block_number),
+            Message::Error {
+                error_code,
+                error_message,
+            } => Fields::Error(*error_code, error_message),
+            Message::OptionAck { options } => Fields::OptionAck(options),
+        }
+    }
+}
+
+impl MessageBuf {
+    pub fn encoded_len(&self) -> usize {
+        self.fields().encoded_len()
+    }
+
+    pub fn validate(&self) -> Result<(), TftpError> {
+        self.fields().validate()
+    }
+
+    pub fn encode_into(&self, buffer: &mut [u8]) -> Result<usize, TftpError> {
+        self.fields().encode_into(buffer)
+    }
+
+    fn fields(&self) -> Fields<'_, String> {
+        match self {
+            MessageBuf::ReadRequest {
+                file_name,
+                mode,
+                options,
+            } => Fields::Request(OpCode::Read, file_name, mode, options),
+            MessageBuf::WriteRequest {
+                file_name,
+                mode,
+                options,
+            } => Fields::Request(OpCode::Write, file_name, mode, options),
+            MessageBuf::Data { block_number, data } => Fields::Data(*block_number, data),
+            MessageBuf::Ack { block_number } => Fields::Ack(*block_number),
+            MessageBuf::Error {
+                error_code,
+                error_message,
+            } => Fields::Error(*error_code, error_message),
+            MessageBuf::OptionAck { options } => Fields::OptionAck(options),
+        }
+    }
+}
+
+impl From<Message<'_>> for MessageBuf {
+    fn from(message: Message<'_>) -> Self {
+        match message {
+            Message::ReadRequest {
+                file_name,
+                mode,
+                options,
+            } => MessageBuf::ReadRequest {
+                file_name: file_name.as_bytes().to_vec(),
+                mode: mode.to_string(),
+                options: owned_options(&options),
+            },
+            Message::WriteRequest {
+                file_name,
+                mode,
+                options,
+            } => MessageBuf::WriteRequest {
+                file_name: file_name.as_bytes().to_vec(),
+                mode: mode.to_string(),
+                options: owned_options(&options),
+            },
+            Message::Data { block_number, data } => MessageBuf::Data {
+                block_number,
+                data: data.to_vec(),
+            },
+            Message::Ack { block_number } => MessageBuf::Ack { block_number },
+            Message::Error {
+                error_code,
+                error_message,
+            } => MessageBuf::Error {
+                error_code,
+                error_message: error_message.to_string(),
+            },
+            Message::OptionAck { options } => MessageBuf::OptionAck {
+                options: owned_options(&options),
+            },
+        }
+    }
+}
+
+fn owned_options(options: &[(&str, &str)]) -> Vec<(String, String)> {
+    options
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+// The fields of either packet type, which is all the encoder needs. Options
+// are borrowed or owned strings depending on the packet type.
+enum Fields<'a, S> {
+    Request(OpCode, &'a [u8], &'a str, &'a [(S, S)]),
+    Data(u16, &'a [u8]),
+    Ack(u16),
+    Error(ErrorCode, &'a str),
+    OptionAck(&'a [(S, S)]),
+}
+
+impl<S: AsRef<str>> Fields<'_, S> {
+    fn encoded_len(&self) -> usize {
+        match self {
+            Fields::Request(_, file_name, mode, options) => {
+                2 + file_name.len() + 1 + mode.len() + 1 + options_len(options)
+            }
+            Fields::Data(_, data) => 4 + data.len(),
+            Fields::Ack(_) => 4,
+            Fields::Error(_, error_message) => 4 + error_message.len() + 1,
+            Fields::OptionAck(options) => 2 + options_len(options),
+        }
+    }
+
+    fn validate(&self) -> Result<(), TftpError> {
+        match self {
+            Fields::Request(_, file_name, mode, options) => {
+                check_bytes("file name", file_name)?;
+                check_string("mode", mode)?;
+                check_options(options)
+            }
+            Fields::Error(_, error_message) => check_string("error message", error_message),
+            Fields::OptionAck(options) => check_options(options),
+            Fields::Data(..) | Fields::Ack(_) => Ok(()),
+        }
+    }
+
+    fn encode_into(&self, buffer: &mut [u8]) -> Result<usize, TftpError> {
+        self.validate()?;
+        let length = self.encoded_len();
+        if buffer.len() < length {
+            return Err(TftpError::protocol(format!(
+                "packet of {} bytes does not fit in {} bytes",
+                length,
+                buffer.len()
+            )));
+        }
+        let mut writer = Writer {
+            buffer,
+            position: 0,
+        };
+        match self {
+            Fields::Request(opcode, file_name, mode, options) => {
+                writer.put_u16(*opcode as u16);
+                writer.put_bytes(file_name);
+                writer.put_bytes(&[0]);
+                writer.put_string(mode);
+                writer.put_options(options);
+            }
+            Fields::Data(block_number, data) => {
+                writer.put_u16(OpCode::Data as u16);
+                writer.put_u16(*block_number);
+                writer.put_bytes(data);
+            }
+            Fields::Ack(block_number) => {
+                writer.put_u16(OpCode::Ack as u16);
+                writer.put_u16(*block_number);
+            }
+            Fields::Error(error_code, error_message) => {
+                writer.put_u16(OpCode::Error as u16);
+                writer.put_u16(u16::from(*error_code));
+                writer.put_string(error_message);
+            }
+            Fields::OptionAck(options) => {
+                writer.put_u16(OpCode::OptionAck as u16);
+                writer.put_options(options);
+            }
+        }
+        Ok(writer.position)
+    }
+}
+
+fn options_len<S: AsRef<str>>(options: &[(S, S)]) -> usize {
+    options
+        .iter()
+        .map(|(name, value)| name.as_ref().len() + 1 + value.as_ref().len() + 1)
+        .sum()
+}
+
+fn check_string(field: &str, value: &str) -> Result<(), TftpError> {
+    check_bytes(field, value.as_bytes())
+}
+
+fn check_bytes(field: &str, value: &[u8]) -> Result<(), TftpError> {
+    if value.contains(&0) {
+        Err(TftpError::protocol(format!(
+            "{} contains a NUL byte",
+            field
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+fn check_options<S: AsRef<str>>(options: &[(S, S)]) -> Result<(), TftpError> {
+    for (name, value) in options {
+        check_string("option name", name.as_ref())?;
+        check_string("option value", value.as_ref())?;
+    }
+    Ok(())
+}
+
+// Fills a buffer already known to be large enough for the packet.
+struct Writer<'a> {
+    buffer: &'a mut [u8],
+    position: usize,
+}
+
+impl Writer<'_> {
+    fn put_bytes(&mut self, bytes: &[u8]) {
+        self.buffer[self.position..self.position + bytes.len()].copy_from_slice(bytes);
+        self.position += bytes.len();
+    }
+
+    fn put_u16(&mut self, value: u16) {
+        self.put_bytes(&value.to_be_bytes());
+    }
+
+    fn put_string(&mut self, value: &str) {
+        self.put_bytes(value.as_bytes());
+        self.put_bytes(&[0]);
+    }
+
+    fn put_options<S: AsRef<str>>(&mut self, options: &[(S, S)]) {
+        for (name, value) in options {
+            self.put_string(name.as_ref());
+            self.put_string(value.as_ref());
+        }
+    }
 }
 
 pub struct TftpSessionInfo {
     pub file_name: String,
-    pub reader: Option<BufReader<File>>,
-    pub writer: Option<BufWriter<File>>,
-    pub block_count: usize,
+    // only used to report progress, drivers decide where packets go
+    pub peer: SocketAddr,
+    // a write request, the peer sends the file
+    pub upload: bool,
+    pub mode: Mode,
+    // options acknowledged by the server
+    pub options: Vec<(String, String)>,
+    // options sent with our own request, an OACK may only narrow them down
+    pub requested: Vec<(String, String)>,
+    pub reader: Option<Box<dyn Read + Send>>,
+    pub writer: Option<Box<dyn Write + Send>>,
+    pub block_size: usize,
+    pub window_size: u16,
+    pub block_number: u16,
+    pub unacknowledged_blocks: u16,
+    pub bytes: u64,
+    // size of the file when the tsize option announced it
+    pub total: Option<u64>,
+    pub on_progress: Option<ProgressCallback>,
+    pub finished: bool,
+    pub state: SessionState,
+    pub timeout: Duration,
+    pub retries: u32,
+    // retransmissions allowed before the session is abandoned
+    pub max_retries: u32,
+    // file an upload replaces once it completes
+    pub local_path: Option<PathBuf>,
+    // file an upload is written to until then, removed if it is aborted
+    pub partial_path: Option<PathBuf>,
+    pub window: VecDeque<(u16, MessageBuf)>,
+    // packets at the front of the window that went out since it was last
+    // queued, the rest wait for the driver to send them
+    pub sent: usize,
+    // packets outside of the window waiting to be sent, such as errors
+    pub outgoing: VecDeque<MessageBuf>,
+    // where `poll_transmit` encodes the packet being sent
+    transmit_buffer: Vec<u8>,
+    // buffers of acknowledged blocks, read into again by `fill_window`
+    spare_blocks: Vec<Vec<u8>>,
+    pub last_sent: Instant,
+    // when the peer last moved the transfer forward
+    pub last_activity: Instant,
+    pub started: Instant,
 }
 
 impl TftpSessionInfo {
     pub fn new() -> Self {
         TftpSessionInfo {
             file_name: String::new(),
+            peer: SocketAddr::from(([0, 0, 0, 0], 0)),
+            upload: false,
+            mode: Mode::default(),
+            options: Vec::new(),
+            requested: Vec::new(),
             reader: None,
             writer: None,
-            block_count: 0,
+            block_size: DEFAULT_BLOCK_SIZE,
+            window_size: DEFAULT_WINDOW_SIZE,
+            block_number: 0,
+            unacknowledged_blocks: 0,
+            bytes: 0,
+            total: None,
+            on_progress: None,
+            finished: false,
+            state: SessionState::default(),
+            timeout: DEFAULT_TIMEOUT,
+            retries: 0,
+            max_retries: DEFAULT_RETRIES,
+            local_path: None,
+            partial_path: None,
+            window: VecDeque::new(),
+            sent: 0,
+            outgoing: VecDeque::new(),
+            transmit_buffer: Vec::new(),
+            spare_blocks: Vec::new(),
+            last_sent: Instant::now(),
+            last_activity: Instant::now(),
+            started: Instant::now(),
         }
     }
 }
+
+impl Default for TftpSessionInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct SessionRegistry {
     sessions: HashMap<SocketAddr, TftpSessionInfo>,
 }
@@ -238,16 +693,47 @@ impl SessionRegistry {
     }
 
     pub fn register(&mut self, address: SocketAddr, session_info: TftpSessionInfo) {
-        if !self.sessions.contains_key(&address) {
-            self.sessions.insert(address, session_info);
-        }
+        self.sessions.entry(address).or_insert(session_info);
     }
 
-    pub fn deregister(&mut self, address: SocketAddr) {
-        self.sessions.remove(&address);
+    pub fn deregister(&mut self, address: SocketAddr) -> Option<TftpSessionInfo> {
+        self.sessions.remove(&address)
     }
 
     pub fn get_session(&mut self, address: SocketAddr) -> Option<&mut TftpSessionInfo> {
         self.sessions.get_mut(&address)
     }
+
+    pub fn sessions_mut(&mut self) -> impl Iterator<Item = (&SocketAddr, &mut TftpSessionInfo)> {
+        self.sessions.iter_mut()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    // Removes and returns the sessions whose peer has gone quiet for too long.
+    pub fn reap(
+        &mut self,
+        now: Instant,
+        session_timeout: Option<Duration>,
+    ) -> Vec<(SocketAddr, TftpSessionInfo)> {
+        self.sessions
+            .extract_if(|_, session_info| session_info.is_idle(now, session_timeout))
+            .collect()
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = (SocketAddr, TftpSessionInfo)> + '_ {
+        self.sessions.drain()
+    }
+}
+
+impl Default for SessionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
 }
@@ -0,0 +1,281 @@
+use crate::audit::Outcome;
+use crate::{ErrorCode, Message, TftpSessionInfo};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+#[cfg(feature = "metrics")]
+use std::io::{BufRead, BufReader, Write};
+#[cfg(feature = "metrics")]
+use std::net::{TcpListener, TcpStream};
+#[cfg(feature = "metrics")]
+use std::sync::Arc;
+
+// Upper bounds of the transfer duration histogram, in seconds.
+const DURATION_BUCKETS: [f64; 10] = [0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0];
+
+const OPCODES: [&str; 6] = ["rrq", "wrq", "data", "ack", "error", "oack"];
+
+// Counters shared by every listener of a server, rendered in the Prometheus
+// text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: [AtomicU64; 6],
+    // keyed by operation
+    completed: Mutex<BTreeMap<&'static str, u64>>,
+    // keyed by operation, who gave up and the error code
+    failed: Mutex<BTreeMap<(&'static str, &'static str, ErrorCode), u64>>,
+    timed_out: Mutex<BTreeMap<&'static str, u64>>,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    retransmissions: AtomicU64,
+    // keyed by listener address
+    active_sessions: Mutex<BTreeMap<SocketAddr, usize>>,
+    durations: Mutex<Histogram>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_packet(&self, message: &Message) {
+        let index = match message {
+            Message::ReadRequest { .. } => 0,
+            Message::WriteRequest { .. } => 1,
+            Message::Data { .. } => 2,
+            Message::Ack { .. } => 3,
+            Message::Error { .. } => 4,
+            Message::OptionAck { .. } => 5,
+        };
+        self.requests[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_retransmission(&self) {
+        self.retransmissions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_active_sessions(&self, listener: SocketAddr, sessions: usize) {
+        lock(&self.active_sessions).insert(listener, sessions);
+    }
+
+    pub fn record_transfer(&self, session_info: &TftpSessionInfo, outcome: &Outcome) {
+        let operation = if session_info.upload {
+            self.bytes_received
+                .fetch_add(session_info.bytes, Ordering::Relaxed);
+            "write"
+        } else {
+            self.bytes_sent
+                .fetch_add(session_info.bytes, Ordering::Relaxed);
+            "read"
+        };
+        match outcome {
+            Outcome::Complete => *lock(&self.completed).entry(operation).or_default() += 1,
+            Outcome::Failed { error_code, .. } => {
+                *lock(&self.failed)
+                    .entry((operation, "server", *error_code))
+                    .or_default() += 1
+            }
+            Outcome::Aborted { error_code, .. } => {
+                *lock(&self.failed)
+                    .entry((operation, "peer", *error_code))
+                    .or_default() += 1
+            }
+            Outcome::TimedOut => *lock(&self.timed_out).entry(operation).or_default() += 1,
+        }
+        lock(&self.durations).observe(session_info.started.elapsed());
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        header(
+            &mut out,
+            "tftp_packets_received_total",
+            "counter",
+            "Packets received, by opcode.",
+        );
+        for (opcode, count) in OPCODES.iter().zip(&self.requests) {
+            let _ = writeln!(
+                out,
+                "tftp_packets_received_total{{opcode=\"{}\"}} {}",
+                opcode,
+                count.load(Ordering::Relaxed)
+            );
+        }
+        header(
+            &mut out,
+            "tftp_transfers_completed_total",
+            "counter",
+            "Transfers that completed.",
+        );
+        for (operation, count) in lock(&self.completed).iter() {
+            let _ = writeln!(
+                out,
+                "tftp_transfers_completed_total{{operation=\"{}\"}} {}",
+                operation, count
+            );
+        }
+        header(
+            &mut out,
+            "tftp_transfers_failed_total",
+            "counter",
+            "Transfers ended by an error packet, by the side that sent it and its code.",
+        );
+        for ((operation, origin, error_code), count) in lock(&self.failed).iter() {
+            let _ = writeln!(
+                out,
+                "tftp_transfers_failed_total{{operation=\"{}\",origin=\"{}\",error_code=\"{}\"}} {}",
+                operation, origin, error_code, count
+            );
+        }
+        header(
+            &mut out,
+            "tftp_transfers_timed_out_total",
+            "counter",
+            "Transfers abandoned after the peer stopped answering.",
+        );
+        for (operation, count) in lock(&self.timed_out).iter() {
+            let _ = writeln!(
+                out,
+                "tftp_transfers_timed_out_total{{operation=\"{}\"}} {}",
+                operation, count
+            );
+        }
+        for (name, help, value) in [
+            (
+                "tftp_bytes_sent_total",
+                "Bytes of finished downloads.",
+                &self.bytes_sent,
+            ),
+            (
+                "tftp_bytes_received_total",
+                "Bytes of finished uploads.",
+                &self.bytes_received,
+            ),
+            (
+                "tftp_retransmissions_total",
+                "Packets sent again after a timeout.",
+                &self.retransmissions,
+            ),
+        ] {
+            header(&mut out, name, "counter", help);
+            let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+        }
+        header(
+            &mut out,
+            "tftp_active_sessions",
+            "gauge",
+            "Transfers in progress, by listener.",
+        );
+        for (listener, sessions) in lock(&self.active_sessions).iter() {
+            let _ = writeln!(
+                out,
+                "tftp_active_sessions{{listener=\"{}\"}} {}",
+                listener, sessions
+            );
+        }
+        header(
+            &mut out,
+            "tftp_transfer_duration_seconds",
+            "histogram",
+            "Duration of finished transfers.",
+        );
+        let durations = lock(&self.durations);
+        for (bound, count) in DURATION_BUCKETS.iter().zip(&durations.buckets) {
+            let _ = writeln!(
+                out,
+                "tftp_transfer_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "tftp_transfer_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            durations.count
+        );
+        let _ = writeln!(out, "tftp_transfer_duration_seconds_sum {}", durations.sum);
+        let _ = writeln!(
+            out,
+            "tftp_transfer_duration_seconds_count {}",
+            durations.count
+        );
+        out
+    }
+}
+
+impl Histogram {
+    // Buckets are cumulative, an observation counts towards every bucket
+    // whose bound it fits under.
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bound, count) in DURATION_BUCKETS.iter().zip(&mut self.buckets) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Answers `GET /metrics` on the listener until the process exits, one
+// connection at a time since scrapes are rare.
+#[cfg(feature = "metrics")]
+pub fn serve(listener: TcpListener, metrics: Arc<Metrics>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(error) = respond(stream, &metrics) {
+                    log::debug!(error:% = error; "metrics request failed");
+                }
+            }
+            Err(error) => log::warn!(error:% = error; "failed to accept a metrics connection"),
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn respond(stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // skip the headers, nothing in them matters here
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render()),
+        (Some("GET"), _) => ("404 Not Found", "Not Found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method Not Allowed\n".to_string()),
+    };
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
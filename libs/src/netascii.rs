@@ -0,0 +1,106 @@
+use std::io::{BufRead, Error, Read, Write};
+
+// Translates local text into netascii while reading: LF becomes CR LF and a
+// bare CR becomes CR NUL.
+pub struct NetasciiReader<R: BufRead> {
+    inner: R,
+    pending: Option<u8>,
+}
+
+impl<R: BufRead> NetasciiReader<R> {
+    pub fn new(inner: R) -> Self {
+        NetasciiReader {
+            inner,
+            pending: None,
+        }
+    }
+}
+
+impl<R: BufRead> Read for NetasciiReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut written = 0;
+        while written < buf.len() {
+            if let Some(byte) = self.pending.take() {
+                buf[written] = byte;
+                written += 1;
+                continue;
+            }
+            let available = self.inner.fill_buf()?;
+            if available.is_empty() {
+                break;
+            }
+            let byte = available[0];
+            self.inner.consume(1);
+            buf[written] = match byte {
+                b'\n' => {
+                    self.pending = Some(b'\n');
+                    b'\r'
+                }
+                b'\r' => {
+                    self.pending = Some(0);
+                    b'\r'
+                }
+                byte => byte,
+            };
+            written += 1;
+        }
+        Ok(written)
+    }
+}
+
+// Translates netascii back into local text while writing: CR LF becomes LF
+// and CR NUL becomes CR. A trailing CR is written out on flush. Blocks are
+// decoded into a buffer kept across writes.
+pub struct NetasciiWriter<W: Write> {
+    inner: W,
+    pending_cr: bool,
+    decoded: Vec<u8>,
+}
+
+impl<W: Write> NetasciiWriter<W> {
+    pub fn new(inner: W) -> Self {
+        NetasciiWriter {
+            inner,
+            pending_cr: false,
+            decoded: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for NetasciiWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let decoded = &mut self.decoded;
+        decoded.clear();
+        for byte in buf {
+            if self.pending_cr {
+                self.pending_cr = false;
+                match byte {
+                    b'\n' => {
+                        decoded.push(b'\n');
+                        continue;
+                    }
+                    0 => {
+                        decoded.push(b'\r');
+                        continue;
+                    }
+                    _ => decoded.push(b'\r'),
+                }
+            }
+            if *byte == b'\r' {
+                self.pending_cr = true;
+            } else {
+                decoded.push(*byte);
+            }
+        }
+        self.inner.write_all(decoded)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.pending_cr {
+            self.pending_cr = false;
+            self.inner.write_all(b"\r")?;
+        }
+        self.inner.flush()
+    }
+}
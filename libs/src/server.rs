@@ -0,0 +1,1192 @@
+use crate::acl::{Acl, AclAction, AclOperation};
+use crate::audit::{AuditLog, Outcome};
+use crate::config::Config;
+use crate::metrics::Metrics;
+use crate::netascii::{NetasciiReader, NetasciiWriter};
+use crate::transfer::{Progress, ProgressCallback, SessionState, TransferProgress};
+use crate::{
+    error_code, extract_message, get_read_file_info, send_queued, send_tftp_message, ErrorCode,
+    FileNameEncoding, Message, MessageBuf, Mode, SessionRegistry, TftpError, TftpSessionInfo,
+    DEFAULT_RETRIES, DEFAULT_TIMEOUT, MAX_BLOCK_SIZE, MAX_TRANSFER_SIZE, MIN_BLOCK_SIZE,
+};
+use log::{debug, error, info, trace, warn};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Error, ErrorKind, Read, Write};
+#[cfg(feature = "metrics")]
+use std::net::TcpListener;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often the receive loop wakes up to retransmit unacknowledged packets.
+const TICK: Duration = Duration::from_millis(250);
+
+pub const DEFAULT_MAX_BLOCK_SIZE: usize = 1468;
+pub const DEFAULT_MAX_WINDOW_SIZE: u16 = 64;
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+const SHUTTING_DOWN: &str = "Server shutting down";
+const TIMED_OUT: &str = "Transfer timed out";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ServerMode {
+    #[default]
+    ReadWrite,
+    // reject every write request
+    ReadOnly,
+    // reject every read request
+    WriteOnly,
+}
+
+impl ServerMode {
+    fn allows(&self, operation: AclOperation) -> bool {
+        !matches!(
+            (self, operation),
+            (ServerMode::ReadOnly, AclOperation::Write)
+                | (ServerMode::WriteOnly, AclOperation::Read)
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub acl: Acl,
+    pub mode: ServerMode,
+    // how requested file names are decoded before they are mapped to paths
+    pub file_name_encoding: FileNameEncoding,
+    pub root: PathBuf,
+    // listeners serving a root other than `root`, keyed by local address
+    pub listener_roots: HashMap<SocketAddr, PathBuf>,
+    // allow write requests to create files that do not exist yet
+    pub create: bool,
+    pub timeout: Duration,
+    pub retries: u32,
+    pub max_block_size: usize,
+    pub max_window_size: u16,
+    // how long a shutdown waits for transfers in progress to finish
+    pub shutdown_timeout: Duration,
+    // stop serving a listener once it has been idle for this long
+    pub idle_timeout: Option<Duration>,
+    // drop a session whose peer has gone quiet for this long, by default once
+    // every retry would have run out
+    pub session_timeout: Option<Duration>,
+    // log level applied by the server binary
+    pub verbosity: u8,
+    pub audit_log: Option<Arc<AuditLog>>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            acl: Acl::new(),
+            mode: ServerMode::default(),
+            file_name_encoding: FileNameEncoding::default(),
+            root: PathBuf::from("."),
+            listener_roots: HashMap::new(),
+            create: false,
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+            max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+            max_window_size: DEFAULT_MAX_WINDOW_SIZE,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            idle_timeout: None,
+            session_timeout: None,
+            verbosity: 0,
+            audit_log: None,
+        }
+    }
+}
+
+pub struct TftpServerBuilder {
+    // addresses to listen on, each with an optional root of its own
+    listeners: Vec<(String, Option<PathBuf>)>,
+    // sockets bound by someone else, such as systemd or inetd
+    sockets: Vec<UdpSocket>,
+    // opened when the server or its settings are built
+    audit_log: Option<PathBuf>,
+    #[cfg(feature = "metrics")]
+    metrics_address: Option<String>,
+    progress: Option<ProgressCallback>,
+    config: ServerConfig,
+}
+
+impl TftpServerBuilder {
+    // Adds an address to listen on, may be called several times.
+    pub fn address(mut self, address: &str) -> Self {
+        self.listeners.push((address.to_string(), None));
+        self
+    }
+
+    // Serves an already bound socket. A listener configured with the same
+    // address reuses it instead of binding again.
+    pub fn socket(mut self, socket: UdpSocket) -> Self {
+        self.sockets.push(socket);
+        self
+    }
+
+    // Adds an address that serves files from its own root directory.
+    pub fn listener<P: AsRef<Path>>(mut self, address: &str, root: P) -> Self {
+        self.listeners
+            .push((address.to_string(), Some(root.as_ref().to_path_buf())));
+        self
+    }
+
+    // Applies every setting present in a configuration file, leaving the
+    // others untouched.
+    pub fn config(mut self, config: &Config) -> Self {
+        for listener in &config.listeners {
+            self.listeners
+                .push((listener.address.to_string(), listener.root.clone()));
+        }
+        if let Some(root) = &config.root {
+            self.config.root = root.clone();
+        }
+        if let Some(mode) = config.mode {
+            self.config.mode = mode;
+        }
+        if let Some(file_name_encoding) = config.file_name_encoding {
+            self.config.file_name_encoding = file_name_encoding;
+        }
+        if let Some(create) = config.create {
+            self.config.create = create;
+        }
+        if let Some(acl) = &config.acl {
+            self.config.acl = acl.clone();
+        }
+        if let Some(max_block_size) = config.max_block_size {
+            self.config.max_block_size = max_block_size;
+        }
+        if let Some(max_window_size) = config.max_window_size {
+            self.config.max_window_size = max_window_size;
+        }
+        if let Some(timeout) = config.timeout {
+            self.config.timeout = timeout;
+        }
+        if let Some(retries) = config.retries {
+            self.config.retries = retries;
+        }
+        if let Some(shutdown_timeout) = config.shutdown_timeout {
+            self.config.shutdown_timeout = shutdown_timeout;
+        }
+        if let Some(idle_timeout) = config.idle_timeout {
+            self.config.idle_timeout = Some(idle_timeout);
+        }
+        if let Some(session_timeout) = config.session_timeout {
+            self.config.session_timeout = Some(session_timeout);
+        }
+        if let Some(verbosity) = config.verbosity {
+            self.config.verbosity = verbosity;
+        }
+        if let Some(audit_log) = &config.audit_log {
+            self.audit_log = Some(audit_log.clone());
+        }
+        self
+    }
+
+    pub fn config_file<P: AsRef<Path>>(self, file_name: P) -> Result<Self, TftpError> {
+        Ok(self.config(&Config::load(file_name)?))
+    }
+
+    pub fn acl(mut self, acl: Acl) -> Self {
+        self.config.acl = acl;
+        self
+    }
+
+    pub fn mode(mut self, mode: ServerMode) -> Self {
+        self.config.mode = mode;
+        self
+    }
+
+    pub fn file_name_encoding(mut self, file_name_encoding: FileNameEncoding) -> Self {
+        self.config.file_name_encoding = file_name_encoding;
+        self
+    }
+
+    pub fn root<P: AsRef<Path>>(mut self, root: P) -> Self {
+        self.config.root = root.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn create(mut self, create: bool) -> Self {
+        self.config.create = create;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = timeout;
+        self
+    }
+
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.config.retries = retries;
+        self
+    }
+
+    pub fn max_block_size(mut self, max_block_size: usize) -> Self {
+        self.config.max_block_size = max_block_size;
+        self
+    }
+
+    pub fn max_window_size(mut self, max_window_size: u16) -> Self {
+        self.config.max_window_size = max_window_size;
+        self
+    }
+
+    pub fn shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.config.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.config.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    pub fn session_timeout(mut self, session_timeout: Duration) -> Self {
+        self.config.session_timeout = Some(session_timeout);
+        self
+    }
+
+    pub fn verbosity(mut self, verbosity: u8) -> Self {
+        self.config.verbosity = verbosity;
+        self
+    }
+
+    // Serves the metrics over HTTP on this address, see `Metrics::render`.
+    #[cfg(feature = "metrics")]
+    pub fn metrics_address(mut self, address: &str) -> Self {
+        self.metrics_address = Some(address.to_string());
+        self
+    }
+
+    // Called as every block of every transfer goes through, with the peer
+    // and file it belongs to.
+    pub fn progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&TransferProgress) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    // Appends a line for every finished transfer to this file.
+    pub fn audit_log<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.audit_log = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    fn validate(&self) -> Result<(), TftpError> {
+        if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&self.config.max_block_size) {
+            return Err(TftpError::config(format!(
+                "maximum block size must be between {} and {}",
+                MIN_BLOCK_SIZE, MAX_BLOCK_SIZE
+            )));
+        }
+        if self.config.max_window_size == 0 {
+            return Err(TftpError::config("maximum window size must be at least 1"));
+        }
+        Ok(())
+    }
+
+    // Produces the settings without binding any socket, for handing to
+    // `ServerHandle::reload` on a running server.
+    pub fn build_config(self) -> Result<ServerConfig, TftpError> {
+        self.validate()?;
+        let mut config = self.config;
+        if let Some(path) = self.audit_log {
+            config.audit_log = Some(Arc::new(AuditLog::open(path)?));
+        }
+        for (address, root) in self.listeners {
+            if let (Ok(address), Some(root)) = (address.parse::<SocketAddr>(), root) {
+                config.listener_roots.insert(address, root);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn build(self) -> Result<TftpServer, TftpError> {
+        self.validate()?;
+        let mut config = self.config;
+        if let Some(path) = self.audit_log {
+            config.audit_log = Some(Arc::new(AuditLog::open(path)?));
+        }
+        #[cfg(feature = "metrics")]
+        let metrics_listener = match &self.metrics_address {
+            Some(address) => Some(TcpListener::bind(address).map_err(|error| {
+                Error::new(
+                    error.kind(),
+                    format!("unable to bind metrics listener {}: {}", address, error),
+                )
+            })?),
+            None => None,
+        };
+        let metrics = Arc::new(Metrics::new());
+        let mut listeners = Vec::new();
+        for socket in self.sockets {
+            socket.set_read_timeout(Some(TICK))?;
+            listeners.push(Listener::new(
+                socket,
+                metrics.clone(),
+                self.progress.clone(),
+            )?);
+        }
+        let mut addresses = self.listeners;
+        if addresses.is_empty() && listeners.is_empty() {
+            addresses.push(("127.0.0.1:69".to_string(), None));
+        }
+        let addresses = addresses
+            .into_iter()
+            .map(|(address, root)| {
+                let resolved = address.to_socket_addrs()?.next().ok_or_else(|| {
+                    TftpError::config(format!("{} does not resolve to an address", address))
+                })?;
+                Ok((resolved, root))
+            })
+            .collect::<Result<Vec<_>, TftpError>>()?;
+        for (address, root) in &addresses {
+            if let Some(listener) = listeners
+                .iter()
+                .find(|listener| listener.local_addr == *address)
+            {
+                if let Some(root) = root {
+                    config
+                        .listener_roots
+                        .insert(listener.local_addr, root.clone());
+                }
+                continue;
+            }
+            // IPv6 sockets also accept IPv4 unless an IPv4 listener claims the port
+            let ipv6_only = addresses
+                .iter()
+                .any(|(other, _)| other.is_ipv4() && other.port() == address.port());
+            let socket = bind(*address, ipv6_only).map_err(|error| {
+                Error::new(
+                    error.kind(),
+                    format!("unable to bind {}: {}", address, error),
+                )
+            })?;
+            socket.set_read_timeout(Some(TICK))?;
+            let listener = Listener::new(socket, metrics.clone(), self.progress.clone())?;
+            if let Some(root) = root {
+                config
+                    .listener_roots
+                    .insert(listener.local_addr, root.clone());
+            }
+            listeners.push(listener);
+        }
+        Ok(TftpServer {
+            listeners,
+            handle: ServerHandle {
+                config: Arc::new(RwLock::new(Arc::new(config))),
+                shutdown_deadline: Arc::new(Mutex::new(None)),
+                metrics,
+            },
+            #[cfg(feature = "metrics")]
+            metrics_listener,
+        })
+    }
+}
+
+// Shared with the listener threads so that the settings can be swapped while
+// the server is running.
+#[derive(Clone)]
+pub struct ServerHandle {
+    config: Arc<RwLock<Arc<ServerConfig>>>,
+    shutdown_deadline: Arc<Mutex<Option<Instant>>>,
+    metrics: Arc<Metrics>,
+}
+
+impl ServerHandle {
+    pub fn config(&self) -> Arc<ServerConfig> {
+        self.config
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    // Replaces the settings applied to new sessions, the ones in progress keep
+    // the settings they started with. Listen addresses cannot change.
+    pub fn reload(&self, config: ServerConfig) {
+        *self.config.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(config);
+    }
+
+    // Stops accepting new requests and gives the transfers in progress until
+    // `grace` has passed to finish, `run` returns once they are all done.
+    // Calling it again can only bring the deadline closer.
+    pub fn shutdown(&self, grace: Duration) {
+        let deadline = Instant::now() + grace;
+        let mut shutdown_deadline = self
+            .shutdown_deadline
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *shutdown_deadline =
+            Some(shutdown_deadline.map_or(deadline, |current| current.min(deadline)));
+    }
+
+    pub fn shutdown_deadline(&self) -> Option<Instant> {
+        *self
+            .shutdown_deadline
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub struct TftpServer {
+    listeners: Vec<Listener>,
+    handle: ServerHandle,
+    #[cfg(feature = "metrics")]
+    metrics_listener: Option<TcpListener>,
+}
+
+impl TftpServer {
+    pub fn builder() -> TftpServerBuilder {
+        TftpServerBuilder {
+            listeners: Vec::new(),
+            sockets: Vec::new(),
+            audit_log: None,
+            #[cfg(feature = "metrics")]
+            metrics_address: None,
+            progress: None,
+            config: ServerConfig::default(),
+        }
+    }
+
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(|listener| listener.socket.local_addr().ok())
+            .collect()
+    }
+
+    pub fn config(&self) -> Arc<ServerConfig> {
+        self.handle.config()
+    }
+
+    pub fn handle(&self) -> ServerHandle {
+        self.handle.clone()
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.handle.metrics.clone()
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_listener
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok())
+    }
+
+    // Serves every listener on its own thread until a shutdown has drained
+    // them all.
+    pub fn run(&mut self) {
+        // left running, scrapes are answered until the process exits
+        #[cfg(feature = "metrics")]
+        if let Some(listener) = self.metrics_listener.take() {
+            let metrics = self.metrics();
+            thread::spawn(move || crate::metrics::serve(listener, metrics));
+        }
+        let handle = &self.handle;
+        thread::scope(|scope| {
+            for listener in &mut self.listeners {
+                scope.spawn(move || listener.run(handle));
+            }
+        });
+    }
+
+    // Handles a datagram as if the first listener had received it from
+    // `source`, replying over its socket. Drives the request handling without
+    // running the server, such as under a fuzzer.
+    pub fn handle_datagram(&mut self, source: SocketAddr, datagram: &[u8]) {
+        let config = self.handle.config();
+        if let Some(listener) = self.listeners.first_mut() {
+            listener.handle_request(&config, source, datagram);
+        }
+    }
+}
+
+struct Listener {
+    socket: UdpSocket,
+    local_addr: SocketAddr,
+    session_registry: SessionRegistry,
+    // the last block of each upload that completed lately and until when
+    // it gets acknowledged again, should the final ACK have been lost
+    finished_uploads: HashMap<SocketAddr, (u16, Instant)>,
+    shutting_down: bool,
+    // when the last packet arrived
+    last_activity: Instant,
+    metrics: Arc<Metrics>,
+    progress: Option<ProgressCallback>,
+}
+
+impl Listener {
+    fn new(
+        socket: UdpSocket,
+        metrics: Arc<Metrics>,
+        progress: Option<ProgressCallback>,
+    ) -> Result<Self, Error> {
+        Ok(Listener {
+            local_addr: socket.local_addr()?,
+            socket,
+            session_registry: SessionRegistry::new(),
+            finished_uploads: HashMap::new(),
+            shutting_down: false,
+            last_activity: Instant::now(),
+            metrics,
+            progress,
+        })
+    }
+
+    fn run(&mut self, handle: &ServerHandle) {
+        // sized for the largest block any configuration may allow
+        let mut buf = vec![0; MAX_BLOCK_SIZE + 4];
+        loop {
+            let config = handle.config();
+            let config = config.as_ref();
+            if let Some(deadline) = handle.shutdown_deadline() {
+                self.shutting_down = true;
+                if self.session_registry.is_empty() {
+                    break;
+                }
+                if Instant::now() >= deadline {
+                    self.abort_sessions(config);
+                    break;
+                }
+            }
+            if let Some(idle_timeout) = config.idle_timeout {
+                if self.session_registry.is_empty() && self.last_activity.elapsed() >= idle_timeout
+                {
+                    info!(listener:% = self.local_addr; "idle, no longer listening");
+                    break;
+                }
+            }
+            match self.socket.recv_from(&mut buf) {
+                Ok((amt, src)) => {
+                    self.last_activity = Instant::now();
+                    self.handle_request(config, src, &buf[..amt])
+                }
+                Err(error)
+                    if error.kind() == ErrorKind::WouldBlock
+                        || error.kind() == ErrorKind::TimedOut => {}
+                Err(error) => {
+                    warn!(listener:% = self.local_addr, error:% = error; "failed to receive data")
+                }
+            }
+            self.retransmit(config);
+            self.reap_idle_sessions(config);
+            let now = Instant::now();
+            self.finished_uploads.retain(|_, (_, until)| *until > now);
+            self.metrics
+                .set_active_sessions(self.local_addr, self.session_registry.len());
+        }
+    }
+
+    // Resends the unacknowledged packets of every session that has not heard
+    // back from its peer within the timeout, dropping the session once
+    // retries run out.
+    fn retransmit(&mut self, config: &ServerConfig) {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        for (address, session_info) in self.session_registry.sessions_mut() {
+            match session_info.handle_timeout(now) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(_) => {
+                    expired.push(*address);
+                    continue;
+                }
+            }
+            self.metrics.record_retransmission();
+            if let Err(error) = send_queued(&self.socket, *address, session_info) {
+                warn!(peer:% = address, error:% = error; "failed to send data");
+            }
+            debug!(
+                peer:% = address,
+                block = session_info.block_number,
+                attempt = session_info.retries;
+                "retransmitted"
+            );
+        }
+        for address in expired {
+            if let Some(session_info) = self.session_registry.deregister(address) {
+                self.abandon(config, address, session_info, TIMED_OUT, Outcome::TimedOut);
+            }
+        }
+    }
+
+    // Gives up on sessions whose peer has stopped moving the transfer
+    // forward, whether or not there is anything left to retransmit.
+    fn reap_idle_sessions(&mut self, config: &ServerConfig) {
+        for (address, session_info) in self
+            .session_registry
+            .reap(Instant::now(), config.session_timeout)
+        {
+            debug!(
+                peer:% = address,
+                idle_s = session_info.last_activity.elapsed().as_secs();
+                "reaping idle session"
+            );
+            self.abandon(config, address, session_info, TIMED_OUT, Outcome::TimedOut);
+        }
+    }
+
+    // Tells every peer still transferring that the server is going away.
+    fn abort_sessions(&mut self, config: &ServerConfig) {
+        let sessions: Vec<_> = self.session_registry.drain().collect();
+        for (address, session_info) in sessions {
+            let outcome = Outcome::Failed {
+                error_code: ErrorCode::NotDefined,
+                error_message: SHUTTING_DOWN.to_string(),
+            };
+            self.abandon(config, address, session_info, SHUTTING_DOWN, outcome);
+        }
+    }
+
+    // Ends a session on the server's side: the peer is told why, the file is
+    // closed and an unfinished upload is removed.
+    fn abandon(
+        &self,
+        config: &ServerConfig,
+        address: SocketAddr,
+        mut session_info: TftpSessionInfo,
+        error_message: &str,
+        outcome: Outcome,
+    ) {
+        reply(
+            &self.socket,
+            address,
+            Message::Error {
+                error_code: ErrorCode::NotDefined,
+                error_message,
+            },
+        );
+        session_info.state = SessionState::Failed;
+        close_files(&mut session_info);
+        self.finish(config, address, &session_info, outcome);
+    }
+
+    // Logs a transfer that came to an end and adds it to the audit log.
+    fn finish(
+        &self,
+        config: &ServerConfig,
+        address: SocketAddr,
+        session_info: &TftpSessionInfo,
+        outcome: Outcome,
+    ) {
+        let duration_ms = session_info.started.elapsed().as_millis() as u64;
+        match &outcome {
+            Outcome::Complete => info!(
+                peer:% = address,
+                file = session_info.file_name.as_str(),
+                bytes = session_info.bytes,
+                duration_ms = duration_ms;
+                "transfer complete"
+            ),
+            outcome => warn!(
+                peer:% = address,
+                file = session_info.file_name.as_str(),
+                bytes = session_info.bytes,
+                duration_ms = duration_ms,
+                outcome:% = outcome;
+                "transfer failed"
+            ),
+        }
+        self.metrics.record_transfer(session_info, &outcome);
+        if let Some(audit_log) = &config.audit_log {
+            if let Err(error) = audit_log.record(address, session_info, &outcome) {
+                error!(
+                    path:% = audit_log.path().display(),
+                    error:% = error;
+                    "failed to write the audit log"
+                );
+            }
+        }
+    }
+
+    // Checks the server mode and the access control list, replying with an
+    // access violation error when the request is denied.
+    fn is_allowed(
+        &self,
+        config: &ServerConfig,
+        source_address: SocketAddr,
+        operation: AclOperation,
+        file_name: &str,
+    ) -> bool {
+        let allowed = if !config.mode.allows(operation) {
+            debug!(
+                peer:% = source_address,
+                file = file_name,
+                operation:? = operation,
+                mode:? = config.mode;
+                "rejected by the server mode"
+            );
+            false
+        } else {
+            let action = config.acl.check(source_address.ip(), operation, file_name);
+            debug!(
+                peer:% = source_address,
+                file = file_name,
+                operation:? = operation,
+                action:? = action;
+                "acl checked"
+            );
+            action == AclAction::Allow
+        };
+        if !allowed {
+            reply(
+                &self.socket,
+                source_address,
+                Message::Error {
+                    error_code: ErrorCode::AccessViolation,
+                    error_message: ErrorCode::AccessViolation.message(),
+                },
+            );
+        }
+        allowed
+    }
+
+    fn handle_request(&mut self, config: &ServerConfig, source_address: SocketAddr, buffer: &[u8]) {
+        let message = match extract_message(buffer) {
+            Ok(message) => message,
+            Err(error) => {
+                debug!(peer:% = source_address, error:% = error; "malformed packet");
+                if self.session_registry.get_session(source_address).is_some() {
+                    self.fail(config, source_address, error);
+                } else {
+                    let (error_code, error_message) = error_code(&error);
+                    let message = Message::Error {
+                        error_code,
+                        error_message: &error_message,
+                    };
+                    reply(&self.socket, source_address, message);
+                }
+                return;
+            }
+        };
+        self.metrics.record_packet(&message);
+        let is_request = matches!(
+            message,
+            Message::ReadRequest { .. } | Message::WriteRequest { .. }
+        );
+        if is_request
+            && self.shutting_down
+            && self.session_registry.get_session(source_address).is_none()
+        {
+            debug!(peer:% = source_address; "request refused while shutting down");
+            reply(
+                &self.socket,
+                source_address,
+                Message::Error {
+                    error_code: ErrorCode::NotDefined,
+                    error_message: SHUTTING_DOWN,
+                },
+            );
+            return;
+        }
+        if is_request && self.session_registry.get_session(source_address).is_some() {
+            // the peer missed the reply, which goes out again on its own
+            trace!(peer:% = source_address; "ignored duplicate request");
+            return;
+        }
+        match &message {
+            Message::ReadRequest {
+                file_name, mode, ..
+            }
+            | Message::WriteRequest {
+                file_name, mode, ..
+            } => {
+                let upload = matches!(message, Message::WriteRequest { .. });
+                let (operation, kind) = if upload {
+                    (AclOperation::Write, "write")
+                } else {
+                    (AclOperation::Read, "read")
+                };
+                info!(
+                    peer:% = source_address,
+                    file:% = file_name,
+                    mode = *mode;
+                    "{} request", kind
+                );
+                // the ACL and the file lookup both see the normalised name
+                let decoded = file_name
+                    .decode(config.file_name_encoding)
+                    .and_then(|decoded| Ok(normalize_path(&decoded)?));
+                let mut session_info = new_session(config);
+                session_info.file_name = match &decoded {
+                    Ok(decoded) => decoded.clone(),
+                    Err(_) => file_name.to_string(),
+                };
+                session_info.peer = source_address;
+                session_info.upload = upload;
+                session_info.mode = Mode::parse(mode).unwrap_or_default();
+                session_info.on_progress = self.progress.clone();
+                if let Err(error) = decoded {
+                    debug!(peer:% = source_address, error:% = error; "refused file name");
+                    let (error_code, error_message) = error_code(&error);
+                    reply(
+                        &self.socket,
+                        source_address,
+                        Message::Error {
+                            error_code,
+                            error_message: &error_message,
+                        },
+                    );
+                    let outcome = Outcome::Failed {
+                        error_code,
+                        error_message,
+                    };
+                    self.finish(config, source_address, &session_info, outcome);
+                    return;
+                }
+                self.finished_uploads.remove(&source_address);
+                if !self.is_allowed(config, source_address, operation, &session_info.file_name) {
+                    let outcome = Outcome::Failed {
+                        error_code: ErrorCode::AccessViolation,
+                        error_message: ErrorCode::AccessViolation.message().to_string(),
+                    };
+                    self.finish(config, source_address, &session_info, outcome);
+                    return;
+                }
+                self.session_registry.register(source_address, session_info);
+            }
+            _ => {}
+        }
+
+        let root = config
+            .listener_roots
+            .get(&self.local_addr)
+            .unwrap_or(&config.root);
+        let udp_socket = &self.socket;
+        let session_info = match self.session_registry.get_session(source_address) {
+            Some(session_info) => session_info,
+            None => {
+                // late or resent blocks of an upload that is over, which
+                // they are all part of
+                if let (Message::Data { .. }, Some((block_number, _))) =
+                    (&message, self.finished_uploads.get(&source_address))
+                {
+                    let block_number = *block_number;
+                    trace!(peer:% = source_address, block = block_number; "acknowledged again");
+                    reply(udp_socket, source_address, Message::Ack { block_number });
+                    return;
+                }
+                debug!(peer:% = source_address; "message from unknown peer");
+                if !matches!(message, Message::Error { .. }) {
+                    reply(
+                        udp_socket,
+                        source_address,
+                        Message::Error {
+                            error_code: ErrorCode::UnknownTransferId,
+                            error_message: ErrorCode::UnknownTransferId.message(),
+                        },
+                    );
+                }
+                return;
+            }
+        };
+        let now = Instant::now();
+        let result = match message {
+            Message::ReadRequest { mode, options, .. } => parse_mode(mode)
+                .and_then(|mode| {
+                    let (reader, file_length) =
+                        get_read_file_info(root.join(&session_info.file_name))?;
+                    let reader: Box<dyn Read + Send> = match mode {
+                        Mode::Netascii => Box::new(NetasciiReader::new(reader)),
+                        Mode::Octet => Box::new(reader),
+                    };
+                    session_info.reader = Some(reader);
+                    session_info.total = Some(file_length);
+                    let accepted =
+                        negotiate_options(config, session_info, &options, Some(file_length));
+                    if accepted.is_empty() {
+                        // Send back the first window of blocks
+                        session_info.fill_window(now)
+                    } else {
+                        session_info.options = accepted.clone();
+                        session_info.send_packet(
+                            now,
+                            0,
+                            MessageBuf::OptionAck { options: accepted },
+                        )
+                    }
+                })
+                .map(|_| Progress::Continue),
+            Message::WriteRequest { mode, options, .. } => parse_mode(mode)
+                .and_then(|mode| {
+                    let path = root.join(&session_info.file_name);
+                    let (file, partial) = open_for_upload(&path, config.create)?;
+                    let writer: Box<dyn Write + Send> = match mode {
+                        Mode::Netascii => Box::new(NetasciiWriter::new(BufWriter::new(file))),
+                        Mode::Octet => Box::new(BufWriter::new(file)),
+                    };
+                    session_info.writer = Some(writer);
+                    session_info.local_path = Some(path);
+                    session_info.partial_path = Some(partial);
+                    let accepted = negotiate_options(config, session_info, &options, None);
+                    let message = if accepted.is_empty() {
+                        MessageBuf::Ack { block_number: 0 }
+                    } else {
+                        session_info.options = accepted.clone();
+                        MessageBuf::OptionAck { options: accepted }
+                    };
+                    session_info.send_packet(now, 0, message)
+                })
+                .map(|_| Progress::Continue),
+            message => session_info.handle_packet(now, message),
+        };
+        // an upload is in place before the ACK of its last block tells the
+        // peer so
+        let result = match result {
+            Ok(Progress::Complete) if session_info.upload => match complete_upload(session_info) {
+                Ok(()) => Ok(Progress::Complete),
+                Err(error) => {
+                    let error = TftpError::from(error);
+                    session_info.fail(&error);
+                    Err(error)
+                }
+            },
+            result => result,
+        };
+        if let Err(error) = send_queued(udp_socket, source_address, session_info) {
+            warn!(peer:% = source_address, error:% = error; "failed to send data");
+        }
+        let outcome = match result {
+            Ok(Progress::Complete) => Outcome::Complete,
+            Ok(Progress::Ignored) => {
+                trace!(peer:% = source_address; "ignored out of order packet");
+                return;
+            }
+            Ok(Progress::Continue) => return,
+            Err(error) => {
+                self.fail(config, source_address, error);
+                return;
+            }
+        };
+        if let Some(session_info) = self.session_registry.deregister(source_address) {
+            if session_info.upload {
+                // as long as the peer may keep resending its last block
+                let until = now + config.timeout * (config.retries + 1);
+                self.finished_uploads
+                    .insert(source_address, (session_info.block_number, until));
+            }
+            self.finish(config, source_address, &session_info, outcome);
+        }
+    }
+
+    // Ends a session over an error, telling the peer why unless the session
+    // already did or the error came from the peer.
+    fn fail(&mut self, config: &ServerConfig, address: SocketAddr, error: TftpError) {
+        let Some(mut session_info) = self.session_registry.deregister(address) else {
+            return;
+        };
+        if !session_info.state.is_over() {
+            session_info.fail(&error);
+        }
+        if let Err(error) = send_queued(&self.socket, address, &mut session_info) {
+            warn!(peer:% = address, error:% = error; "failed to send data");
+        }
+        close_files(&mut session_info);
+        let outcome = match error {
+            TftpError::Remote {
+                error_code,
+                error_message,
+            } => Outcome::Aborted {
+                error_code,
+                error_message,
+            },
+            error => {
+                debug!(peer:% = address, error:% = error; "sending error");
+                let (error_code, error_message) = error_code(&error);
+                Outcome::Failed {
+                    error_code,
+                    error_message,
+                }
+            }
+        };
+        self.finish(config, address, &session_info, outcome);
+    }
+}
+
+// Closes the files of a session that ended early, removing an unfinished
+// upload. The file it was to replace is left as it was.
+fn close_files(session_info: &mut TftpSessionInfo) {
+    session_info.reader = None;
+    session_info.writer = None;
+    if let Some(path) = session_info.partial_path.take() {
+        if let Err(error) = fs::remove_file(&path) {
+            warn!(path:% = path.display(), error:% = error; "failed to remove partial upload");
+        }
+    }
+}
+
+// Moves a completed upload over the file it replaces.
+fn complete_upload(session_info: &mut TftpSessionInfo) -> Result<(), Error> {
+    if let Some(mut writer) = session_info.writer.take() {
+        writer.flush()?;
+    }
+    match (&session_info.partial_path, &session_info.local_path) {
+        (Some(partial), Some(path)) => fs::rename(partial, path)?,
+        _ => return Ok(()),
+    }
+    session_info.partial_path = None;
+    Ok(())
+}
+
+// Sends a packet outside of any session, a failure only gets logged since the
+// peer retries or gives up on its own.
+fn reply(udp_socket: &UdpSocket, destination: SocketAddr, message: Message) {
+    if let Err(error) = send_tftp_message(udp_socket, message, destination) {
+        warn!(peer:% = destination, error:% = error; "failed to send data");
+    }
+}
+
+fn bind(address: SocketAddr, ipv6_only: bool) -> Result<UdpSocket, Error> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::DGRAM,
+        Some(Protocol::UDP),
+    )?;
+    if address.is_ipv6() {
+        socket.set_only_v6(ipv6_only)?;
+    }
+    socket.bind(&address.into())?;
+    Ok(socket.into())
+}
+
+fn new_session(config: &ServerConfig) -> TftpSessionInfo {
+    let mut session_info = TftpSessionInfo::new();
+    session_info.timeout = config.timeout;
+    session_info.max_retries = config.retries;
+    session_info
+}
+
+fn parse_mode(mode: &str) -> Result<Mode, TftpError> {
+    Mode::parse(mode)
+        .ok_or_else(|| TftpError::protocol(format!("unsupported transfer mode {}", mode)))
+}
+
+// Applies the options the server supports and returns the ones to acknowledge,
+// unknown or invalid options are ignored as RFC 2347 requires.
+fn negotiate_options(
+    config: &ServerConfig,
+    session_info: &mut TftpSessionInfo,
+    options: &[(&str, &str)],
+    file_length: Option<u64>,
+) -> Vec<(String, String)> {
+    let mut accepted = Vec::new();
+    for (name, value) in options {
+        // option names are case insensitive
+        let name = name.to_ascii_lowercase();
+        match name.as_str() {
+            "blksize" => {
+                if let Ok(block_size) = value.parse::<usize>() {
+                    if block_size >= MIN_BLOCK_SIZE {
+                        session_info.block_size = block_size.min(config.max_block_size);
+                        accepted.push((name.clone(), session_info.block_size.to_string()));
+                    }
+                }
+            }
+            "timeout" => {
+                if let Ok(seconds) = value.parse::<u64>() {
+                    if (1..=255).contains(&seconds) {
+                        session_info.timeout = Duration::from_secs(seconds);
+                        accepted.push((name.clone(), value.to_string()));
+                    }
+                }
+            }
+            "windowsize" => {
+                if let Ok(window_size) = value.parse::<u16>() {
+                    if window_size >= 1 {
+                        session_info.window_size = window_size.min(config.max_window_size);
+                        accepted.push((name.clone(), session_info.window_size.to_string()));
+                    }
+                }
+            }
+            "tsize" => match file_length {
+                Some(file_length) => accepted.push((name.clone(), file_length.to_string())),
+                // the size of an upload, ignored when it makes no sense
+                None => {
+                    if let Ok(size) = value.parse::<u64>() {
+                        if size <= MAX_TRANSFER_SIZE {
+                            session_info.total = Some(size);
+                            accepted.push((name.clone(), value.to_string()));
+                        }
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+    if !accepted.is_empty() {
+        debug!(options:? = accepted; "negotiated options");
+    }
+    accepted
+}
+
+// Turns a decoded file name into a path relative to the server root, so that
+// `/a/./b` and `a/b` name the same file for the ACL and the lookup alike.
+// Refuses anything that would escape the root and control characters no sane
+// file name contains.
+fn normalize_path(file_name: &str) -> Result<String, Error> {
+    if file_name.chars().any(char::is_control) {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} contains control characters", file_name.escape_debug()),
+        ));
+    }
+    let outside = || {
+        Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} is outside the server root", file_name),
+        )
+    };
+    let mut components = Vec::new();
+    for component in Path::new(file_name).components() {
+        match component {
+            Component::Normal(component) => components.push(component.to_string_lossy()),
+            // a leading slash stands for the root, PXE clients send one
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return Err(outside()),
+        }
+    }
+    if components.is_empty() {
+        return Err(outside());
+    }
+    Ok(components.join("/"))
+}
+
+// Opens the hidden file an upload to `path` is written to, next to it for the
+// final rename to stay on one file system. The target is only checked: it
+// must be writable, and exist unless uploads may create files.
+fn open_for_upload(path: &Path, create: bool) -> Result<(File, PathBuf), Error> {
+    let permissions = match OpenOptions::new().write(true).open(path) {
+        Ok(file) => Some(file.metadata()?.permissions()),
+        Err(error) if create && error.kind() == ErrorKind::NotFound => None,
+        Err(error) => return Err(error),
+    };
+    let partial = partial_path(path);
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&partial)?;
+    if let Some(permissions) = permissions {
+        if let Err(error) = file.set_permissions(permissions) {
+            let _ = fs::remove_file(&partial);
+            return Err(error);
+        }
+    }
+    Ok((file, partial))
+}
+
+// A name no other upload uses, even one to the same file.
+fn partial_path(path: &Path) -> PathBuf {
+    static UPLOADS: AtomicU64 = AtomicU64::new(0);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let upload = UPLOADS.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{}.{}.{}.part", name, std::process::id(), upload))
+}
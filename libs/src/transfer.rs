@@ -0,0 +1,533 @@
+use crate::{
+    error_code, extract_message, read_block_into, ErrorCode, Message, MessageBuf, TftpError,
+    TftpSessionInfo, MAX_TRANSFER_SIZE, MIN_BLOCK_SIZE,
+};
+use log::{debug, trace};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Outcome of feeding a DATA or ACK packet into a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    Continue,
+    Complete,
+    Ignored,
+}
+
+// Where a session stands, which decides the packets the peer may send next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    // the request is out, the peer may answer with an OACK or go straight
+    // to the transfer: DATA 1 for a download, ACK 0 for an upload
+    AwaitingOack { sending: bool },
+    // blocks up to this one were sent, block 0 being the OACK
+    Sending(u16),
+    // blocks up to this one were received and written, block 0 being the
+    // request or the OACK
+    Receiving(u16),
+    // the last block was sent and only its ACK is missing
+    Finishing,
+    Done,
+    Failed,
+}
+
+// A session that has sent nothing yet. Server sessions answer the request
+// before hearing from the peer again, so the direction does not matter there.
+impl Default for SessionState {
+    fn default() -> Self {
+        SessionState::AwaitingOack { sending: false }
+    }
+}
+
+impl SessionState {
+    pub fn is_over(&self) -> bool {
+        matches!(self, SessionState::Done | SessionState::Failed)
+    }
+
+    // Checks a packet from the peer against the state. Anything the state
+    // does not expect is an illegal TFTP operation, reported as ERROR 4.
+    pub fn accepts(&self, message: &Message) -> Result<(), TftpError> {
+        let allowed = match (self, message) {
+            (state, Message::Error { .. }) => !state.is_over(),
+            (
+                SessionState::AwaitingOack { sending: true },
+                Message::OptionAck { .. } | Message::Ack { .. },
+            ) => true,
+            (
+                SessionState::AwaitingOack { sending: false },
+                Message::OptionAck { .. } | Message::Data { .. },
+            ) => true,
+            (SessionState::Sending(_) | SessionState::Finishing, Message::Ack { .. }) => true,
+            (SessionState::Receiving(_), Message::Data { .. }) => true,
+            _ => false,
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(TftpError::protocol(format!(
+                "unexpected {} while {}",
+                packet_name(message),
+                self
+            )))
+        }
+    }
+
+    // The state after sending a packet that starts or answers a step of the
+    // transfer, data blocks are tracked by `fill_window` instead.
+    fn after_sending(self, message: &MessageBuf, sending: bool) -> Self {
+        match message {
+            MessageBuf::ReadRequest { .. } => SessionState::AwaitingOack { sending: false },
+            MessageBuf::WriteRequest { .. } => SessionState::AwaitingOack { sending: true },
+            MessageBuf::OptionAck { .. } if sending => SessionState::Sending(0),
+            MessageBuf::OptionAck { .. } => SessionState::Receiving(0),
+            MessageBuf::Ack { block_number } => SessionState::Receiving(*block_number),
+            MessageBuf::Data { .. } | MessageBuf::Error { .. } => self,
+        }
+    }
+}
+
+impl fmt::Display for SessionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionState::AwaitingOack { sending: true } => {
+                write!(f, "awaiting the option acknowledgement of an upload")
+            }
+            SessionState::AwaitingOack { sending: false } => {
+                write!(f, "awaiting the option acknowledgement of a download")
+            }
+            SessionState::Sending(block) => write!(f, "sending block {}", block),
+            SessionState::Receiving(block) => write!(f, "receiving after block {}", block),
+            SessionState::Finishing => write!(f, "waiting for the last acknowledgement"),
+            SessionState::Done => write!(f, "done"),
+            SessionState::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+fn packet_name(message: &Message) -> &'static str {
+    match message {
+        Message::ReadRequest { .. } => "RRQ",
+        Message::WriteRequest { .. } => "WRQ",
+        Message::Data { .. } => "DATA",
+        Message::Ack { .. } => "ACK",
+        Message::Error { .. } => "ERROR",
+        Message::OptionAck { .. } => "OACK",
+    }
+}
+
+// Called after every block that moves a transfer forward.
+pub type ProgressCallback = Arc<dyn Fn(&TransferProgress) + Send + Sync>;
+
+// Where a transfer stands, `total` is only known when the file size was
+// exchanged with the tsize option.
+#[derive(Debug, Clone, Copy)]
+pub struct TransferProgress<'a> {
+    pub peer: SocketAddr,
+    pub file_name: &'a str,
+    pub bytes: u64,
+    pub total: Option<u64>,
+    pub elapsed: Duration,
+}
+
+impl TransferProgress<'_> {
+    // Bytes per second since the transfer started.
+    pub fn rate(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.bytes as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    pub fn fraction(&self) -> Option<f64> {
+        self.total
+            .filter(|total| *total > 0)
+            .map(|total| (self.bytes as f64 / total as f64).min(1.0))
+    }
+
+    // Time left at the average rate so far.
+    pub fn eta(&self) -> Option<Duration> {
+        let remaining = self.total?.saturating_sub(self.bytes);
+        let rate = self.rate();
+        if rate > 0.0 {
+            Duration::try_from_secs_f64(remaining as f64 / rate).ok()
+        } else {
+            None
+        }
+    }
+}
+
+// The protocol core of a transfer. Sessions never touch a socket: drivers feed
+// them packets from the peer and the passing of time, then send whatever
+// `poll_transmit` hands out and wake up again at `poll_timeout`.
+//
+// The window holds every packet sent but not acknowledged yet, keyed by block
+// number. Requests, ACKs and OACKs take the place of the whole window. Once a
+// transfer is under way, the window and the buffers of its blocks are reused
+// so that moving a block along allocates nothing.
+impl TftpSessionInfo {
+    // Parses and handles a datagram from the peer.
+    pub fn handle_datagram(&mut self, now: Instant, packet: &[u8]) -> Result<Progress, TftpError> {
+        match extract_message(packet) {
+            Ok(message) => self.handle_packet(now, message),
+            Err(error) => {
+                self.fail(&error);
+                Err(error)
+            }
+        }
+    }
+
+    // Handles a packet from the peer. A failure queues the error packet that
+    // tells the peer why, except for an ERROR from the peer itself.
+    pub fn handle_packet(&mut self, now: Instant, message: Message) -> Result<Progress, TftpError> {
+        if let Message::OptionAck { .. } = message {
+            if self.repeats_oack() {
+                return Ok(self.handle_repeated_oack());
+            }
+        }
+        let result = self.state.accepts(&message).and_then(|_| match message {
+            Message::OptionAck { options } => self.handle_oack(now, options),
+            Message::Data { block_number, data } => self.handle_data(now, block_number, data),
+            Message::Ack { block_number } => self.handle_ack(now, block_number),
+            Message::Error {
+                error_code,
+                error_message,
+            } => {
+                self.state = SessionState::Failed;
+                Err(TftpError::Remote {
+                    error_code,
+                    error_message: error_message.to_string(),
+                })
+            }
+            // `accepts` turns requests away in every state
+            Message::ReadRequest { .. } | Message::WriteRequest { .. } => Ok(Progress::Ignored),
+        });
+        if let Err(error) = &result {
+            if !self.state.is_over() {
+                self.fail(error);
+            }
+        }
+        result
+    }
+
+    // Resends the window once the peer has been quiet for the timeout,
+    // returning whether anything went out again. Running out of retries
+    // fails the session without telling the peer, which is gone anyway.
+    pub fn handle_timeout(&mut self, now: Instant) -> Result<bool, TftpError> {
+        if !self.needs_retransmit(now) {
+            return Ok(false);
+        }
+        if self.retries >= self.max_retries {
+            self.state = SessionState::Failed;
+            return Err(TftpError::TimedOut("transfer timed out".to_string()));
+        }
+        self.retries += 1;
+        self.last_sent = now;
+        self.sent = 0;
+        Ok(true)
+    }
+
+    // The next packet to send to the peer, encoded into a buffer the session
+    // keeps from one packet to the next.
+    pub fn poll_transmit(&mut self) -> Option<&[u8]> {
+        let length = match self.outgoing.pop_front() {
+            Some(message) => encode(&mut self.transmit_buffer, &message),
+            None => {
+                let (_, message) = self.window.get(self.sent)?;
+                self.sent += 1;
+                encode(&mut self.transmit_buffer, message)
+            }
+        }?;
+        Some(&self.transmit_buffer[..length])
+    }
+
+    // When `handle_timeout` has to run next, `None` while nothing waits for
+    // an answer.
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        if self.window.is_empty() || self.state.is_over() {
+            None
+        } else {
+            Some(self.last_sent + self.timeout)
+        }
+    }
+
+    // Ends the session over an error on this side, queueing the error packet.
+    pub fn fail(&mut self, error: &TftpError) {
+        let (error_code, error_message) = error_code(error);
+        self.state = SessionState::Failed;
+        self.clear_window();
+        self.outgoing.push_back(MessageBuf::Error {
+            error_code,
+            // the message must survive the trip through `poll_transmit`
+            error_message: error_message.replace('\0', ""),
+        });
+    }
+
+    // Queues a packet that replaces the whole window, failing if it cannot
+    // be encoded. Packets built to be kept can be passed owned already.
+    pub fn send_packet<M: Into<MessageBuf>>(
+        &mut self,
+        now: Instant,
+        block_number: u16,
+        message: M,
+    ) -> Result<(), TftpError> {
+        let message = message.into();
+        message.validate()?;
+        if let MessageBuf::ReadRequest { options, .. } | MessageBuf::WriteRequest { options, .. } =
+            &message
+        {
+            self.requested = options.clone();
+        }
+        self.state = self.state.after_sending(&message, self.reader.is_some());
+        self.clear_window();
+        self.window.push_back((block_number, message));
+        self.last_sent = now;
+        self.retries = 0;
+        Ok(())
+    }
+
+    // Reads and queues blocks until the window is full or the file is exhausted.
+    pub fn fill_window(&mut self, now: Instant) -> Result<(), TftpError> {
+        while self.window.len() < self.window_size as usize && !self.finished {
+            let reader = self
+                .reader
+                .as_mut()
+                .ok_or_else(|| TftpError::protocol("not sending a file"))?;
+            let mut contents = self.spare_blocks.pop().unwrap_or_default();
+            read_block_into(reader, &mut contents, self.block_size)?;
+            self.finished = contents.len() < self.block_size;
+            self.block_number = self.block_number.wrapping_add(1);
+            self.bytes += contents.len() as u64;
+            let packet = MessageBuf::Data {
+                block_number: self.block_number,
+                data: contents,
+            };
+            self.window.push_back((self.block_number, packet));
+        }
+        self.state = if self.finished {
+            SessionState::Finishing
+        } else {
+            SessionState::Sending(self.block_number)
+        };
+        self.last_sent = now;
+        Ok(())
+    }
+
+    // Applies the options acknowledged by the server, which may only narrow
+    // down the ones requested, then starts the transfer proper.
+    fn handle_oack(
+        &mut self,
+        now: Instant,
+        options: Vec<(&str, &str)>,
+    ) -> Result<Progress, TftpError> {
+        let mut negotiated = Vec::with_capacity(options.len());
+        for (name, value) in options {
+            // option names are case insensitive
+            let name = name.to_ascii_lowercase();
+            let requested = self
+                .requested
+                .iter()
+                .find(|(requested, _)| requested.eq_ignore_ascii_case(&name))
+                .and_then(|(_, requested)| requested.parse::<u64>().ok());
+            match (name.as_str(), value.parse::<u64>()) {
+                ("blksize", Ok(block_size))
+                    if block_size >= MIN_BLOCK_SIZE as u64 && Some(block_size) <= requested =>
+                {
+                    self.block_size = block_size as usize
+                }
+                ("windowsize", Ok(window_size))
+                    if window_size >= 1 && Some(window_size) <= requested =>
+                {
+                    self.window_size = window_size as u16
+                }
+                ("timeout", Ok(seconds)) if Some(seconds) == requested => {
+                    self.timeout = Duration::from_secs(seconds)
+                }
+                // an upload only gets its own size echoed back
+                ("tsize", Ok(size)) if requested.is_some() && size <= MAX_TRANSFER_SIZE => {
+                    if self.writer.is_some() {
+                        self.total = Some(size)
+                    }
+                }
+                _ => {
+                    self.state = SessionState::Failed;
+                    self.clear_window();
+                    self.outgoing.push_back(MessageBuf::Error {
+                        error_code: ErrorCode::OptionNegotiation,
+                        error_message: format!("Unexpected option {}={}", name, value),
+                    });
+                    return Err(TftpError::protocol(format!(
+                        "server acknowledged unexpected option {}={}",
+                        name, value
+                    )));
+                }
+            };
+            debug!(option = name.as_str(), value = value; "negotiated");
+            negotiated.push((name, value.to_string()));
+        }
+        self.options = negotiated;
+        if self.reader.is_some() {
+            self.handle_ack(now, 0)
+        } else {
+            self.send_packet(now, 0, Message::Ack { block_number: 0 })?;
+            Ok(Progress::Continue)
+        }
+    }
+
+    // Whether an OACK is the server sending it again, once this side asked
+    // for options and moved on.
+    fn repeats_oack(&self) -> bool {
+        !self.requested.is_empty()
+            && matches!(
+                self.state,
+                SessionState::Receiving(_) | SessionState::Sending(_) | SessionState::Finishing
+            )
+    }
+
+    // The server resends its OACK when it missed the answer, ACK 0 or the
+    // first blocks of an upload, which go out again. Past those it is only a
+    // late duplicate.
+    fn handle_repeated_oack(&mut self) -> Progress {
+        let unanswered = match self.state {
+            SessionState::Receiving(block_number) => block_number == 0,
+            _ => self.window.front().is_some_and(|(block, _)| *block == 1),
+        };
+        if unanswered {
+            self.sent = 0;
+        }
+        Progress::Ignored
+    }
+
+    // Slides the window past an acknowledged block, resending whatever the
+    // receiver is still missing and topping the window back up.
+    fn handle_ack(&mut self, now: Instant, block_number: u16) -> Result<Progress, TftpError> {
+        trace!(peer:% = self.peer, block = block_number; "ack");
+        if !self.window.iter().any(|(block, _)| *block == block_number) {
+            return Ok(Progress::Ignored);
+        }
+        while let Some((block, packet)) = self.window.pop_front() {
+            self.recycle(packet);
+            if block == block_number {
+                break;
+            }
+        }
+        self.retries = 0;
+        self.last_activity = now;
+        self.report_progress(now);
+        if self.window.is_empty() && self.finished {
+            self.state = SessionState::Done;
+            return Ok(Progress::Complete);
+        }
+        self.sent = 0;
+        self.fill_window(now)?;
+        Ok(Progress::Continue)
+    }
+
+    // Writes the next expected block, acknowledging once per window or at the
+    // end of the file. Anything out of order re-acknowledges the last good block.
+    fn handle_data(
+        &mut self,
+        now: Instant,
+        block_number: u16,
+        data: &[u8],
+    ) -> Result<Progress, TftpError> {
+        trace!(peer:% = self.peer, block = block_number, length = data.len(); "data");
+        if block_number != self.block_number.wrapping_add(1) {
+            self.unacknowledged_blocks = 0;
+            let last_block = self.block_number;
+            self.send_packet(
+                now,
+                last_block,
+                Message::Ack {
+                    block_number: last_block,
+                },
+            )?;
+            return Ok(Progress::Ignored);
+        }
+        let writer = self
+            .writer
+            .as_mut()
+            .ok_or_else(|| TftpError::protocol("not receiving a file"))?;
+        writer.write_all(data)?;
+        let last_block = data.len() < self.block_size;
+        if last_block {
+            writer.flush()?;
+        }
+        self.block_number = block_number;
+        self.state = SessionState::Receiving(block_number);
+        self.bytes += data.len() as u64;
+        self.unacknowledged_blocks += 1;
+        self.last_sent = now;
+        self.last_activity = now;
+        self.retries = 0;
+        self.report_progress(now);
+        if last_block || self.unacknowledged_blocks >= self.window_size {
+            self.unacknowledged_blocks = 0;
+            self.send_packet(now, block_number, Message::Ack { block_number })?;
+        }
+        if last_block {
+            self.finished = true;
+            self.state = SessionState::Done;
+            return Ok(Progress::Complete);
+        }
+        Ok(Progress::Continue)
+    }
+
+    // Uploads count what the peer has acknowledged, the window still in flight
+    // is not there yet.
+    fn report_progress(&self, now: Instant) {
+        if let Some(on_progress) = &self.on_progress {
+            let in_flight: u64 = self
+                .window
+                .iter()
+                .filter_map(|(_, packet)| match packet {
+                    MessageBuf::Data { data, .. } => Some(data.len() as u64),
+                    _ => None,
+                })
+                .sum();
+            on_progress(&TransferProgress {
+                peer: self.peer,
+                file_name: &self.file_name,
+                bytes: self.bytes - in_flight.min(self.bytes),
+                total: self.total,
+                elapsed: now.duration_since(self.started),
+            });
+        }
+    }
+
+    fn clear_window(&mut self) {
+        while let Some((_, packet)) = self.window.pop_front() {
+            self.recycle(packet);
+        }
+        self.sent = 0;
+    }
+
+    // Keeps the buffer of a block that left the window for the next one,
+    // there are never more of them than fit in a window.
+    fn recycle(&mut self, packet: MessageBuf) {
+        if let MessageBuf::Data { data, .. } = packet {
+            if self.spare_blocks.len() < self.window_size as usize {
+                self.spare_blocks.push(data);
+            }
+        }
+    }
+
+    // Without a session timeout the peer gets as long as every retry takes.
+    pub fn is_idle(&self, now: Instant, session_timeout: Option<Duration>) -> bool {
+        let limit = session_timeout.unwrap_or(self.timeout * (self.max_retries + 1));
+        now.duration_since(self.last_activity) >= limit
+    }
+
+    pub fn needs_retransmit(&self, now: Instant) -> bool {
+        !self.window.is_empty() && now.duration_since(self.last_sent) >= self.timeout
+    }
+}
+
+// Encodes a queued packet, which was validated when it was queued.
+fn encode(buffer: &mut Vec<u8>, message: &MessageBuf) -> Option<usize> {
+    buffer.resize(message.encoded_len(), 0);
+    message.encode_into(buffer).ok()
+}
@@ -0,0 +1,223 @@
+// Access control lists on their own, without a server in front of them.
+
+use std::net::IpAddr;
+use tftp_libs::acl::{Acl, AclAction, AclOperation, Cidr};
+
+fn ip(address: &str) -> IpAddr {
+    address.parse().unwrap()
+}
+
+fn contains(network: &str, address: &str) -> bool {
+    Cidr::parse(network).unwrap().contains(ip(address))
+}
+
+// Whether a read of `path` from 10.0.0.1 passes a list made of one glob.
+fn glob_allows(glob: &str, path: &str) -> bool {
+    let acl = Acl::parse(&format!("allow read 0.0.0.0/0 {}", glob)).unwrap();
+    acl.check(ip("10.0.0.1"), AclOperation::Read, path) == AclAction::Allow
+}
+
+#[test]
+fn prefix_zero_contains_its_whole_family() {
+    assert!(contains("0.0.0.0/0", "0.0.0.0"));
+    assert!(contains("0.0.0.0/0", "255.255.255.255"));
+    assert!(contains("10.0.0.0/0", "192.168.1.1"));
+    assert!(contains("::/0", "::1"));
+    assert!(contains("::/0", "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"));
+    assert!(!contains("0.0.0.0/0", "2001:db8::1"));
+    assert!(!contains("::/0", "10.0.0.1"));
+}
+
+#[test]
+fn full_prefix_contains_one_address() {
+    assert!(contains("10.0.0.1/32", "10.0.0.1"));
+    assert!(!contains("10.0.0.1/32", "10.0.0.2"));
+    assert!(contains("10.0.0.1", "10.0.0.1"));
+    assert!(!contains("10.0.0.1", "10.0.0.0"));
+    assert!(contains("2001:db8::1/128", "2001:db8::1"));
+    assert!(!contains("2001:db8::1/128", "2001:db8::2"));
+    assert!(contains("2001:db8::1", "2001:db8::1"));
+    assert!(!contains("2001:db8::1", "2001:db8::"));
+}
+
+#[test]
+fn partial_prefixes_ignore_the_host_bits() {
+    assert!(contains("10.1.2.3/8", "10.255.0.1"));
+    assert!(!contains("10.0.0.0/8", "11.0.0.0"));
+    assert!(contains("192.168.1.128/25", "192.168.1.255"));
+    assert!(!contains("192.168.1.128/25", "192.168.1.127"));
+    assert!(contains("2001:db8::/32", "2001:db8:ffff::1"));
+    assert!(!contains("2001:db8::/32", "2001:db9::1"));
+}
+
+// IPv4 clients of a dual-stack socket arrive as ::ffff:a.b.c.d.
+#[test]
+fn mapped_addresses_match_ipv4_networks() {
+    assert!(contains("10.0.0.0/8", "::ffff:10.1.2.3"));
+    assert!(!contains("10.0.0.0/8", "::ffff:11.1.2.3"));
+    assert!(contains("0.0.0.0/0", "::ffff:192.168.1.1"));
+    assert!(contains("10.0.0.1/32", "::ffff:10.0.0.1"));
+    assert!(!contains("2001:db8::/32", "::ffff:10.0.0.1"));
+}
+
+#[test]
+fn invalid_networks_are_refused() {
+    for network in [
+        "10.0.0.0/33",
+        "::/129",
+        "10.0.0.0/",
+        "10.0.0.0/-1",
+        "10.0.0.0/8/8",
+        "10.0.0/8",
+        "example.com/8",
+        "",
+    ] {
+        assert!(Cidr::parse(network).is_err(), "{}", network);
+    }
+}
+
+#[test]
+fn star_matches_across_slashes() {
+    assert!(glob_allows("*", "boot.img"));
+    assert!(glob_allows("*", "pxe/efi/boot.img"));
+    assert!(glob_allows("pxe/*", "pxe/efi/boot.img"));
+    assert!(glob_allows("*.img", "pxe/efi/boot.img"));
+    assert!(glob_allows("pxe/*", "pxe/"));
+    assert!(!glob_allows("pxe/*", "pxe"));
+    assert!(!glob_allows("pxe/*", "other/pxe/boot.img"));
+}
+
+#[test]
+fn question_mark_matches_one_byte() {
+    assert!(glob_allows("boot?.img", "boot1.img"));
+    assert!(glob_allows("boot?.img", "boot/.img"));
+    assert!(!glob_allows("boot?.img", "boot.img"));
+    assert!(!glob_allows("boot?.img", "boot12.img"));
+    assert!(glob_allows("????", "abcd"));
+    assert!(!glob_allows("????", "abc"));
+}
+
+#[test]
+fn globs_backtrack() {
+    // the first `b` the star stops at is not the one that matches
+    assert!(glob_allows("a*b", "abab"));
+    assert!(glob_allows("*a*b*c", "xaybbzc"));
+    assert!(glob_allows("*.tar.gz", "backup.tar.tar.gz"));
+    assert!(glob_allows("a*?c", "abbc"));
+    assert!(glob_allows("**", ""));
+    assert!(!glob_allows("a*b", "abac"));
+    assert!(!glob_allows("*.tar.gz", "backup.tar.gz.bak"));
+    assert!(!glob_allows("a*?c", "ac"));
+}
+
+#[test]
+fn glob_without_wildcards_matches_exactly() {
+    assert!(glob_allows("pxe/boot.img", "pxe/boot.img"));
+    assert!(!glob_allows("pxe/boot.img", "pxe/boot.im"));
+    assert!(!glob_allows("pxe/boot.img", "pxe/boot.img2"));
+    assert!(!glob_allows("pxe/boot.img", "PXE/boot.img"));
+}
+
+#[test]
+fn empty_list_allows_everything() {
+    let acl = Acl::parse("# nothing but a comment\n\n").unwrap();
+    assert!(acl.is_empty());
+    assert_eq!(
+        acl.check(ip("192.0.2.1"), AclOperation::Write, "anything"),
+        AclAction::Allow
+    );
+}
+
+#[test]
+fn first_matching_rule_wins() {
+    let acl = Acl::parse(
+        "deny  any   10.0.0.66/32 *\n\
+         allow read  10.0.0.0/8   pxe/*\n\
+         deny  read  0.0.0.0/0    pxe/*\n\
+         allow write 10.0.0.0/8   upload/*\n\
+         allow any   0.0.0.0/0    public/*\n",
+    )
+    .unwrap();
+    let check = |address: &str, operation, path: &str| acl.check(ip(address), operation, path);
+
+    assert_eq!(
+        check("10.0.0.1", AclOperation::Read, "pxe/boot.img"),
+        AclAction::Allow
+    );
+    // denied by the first rule before the second could allow it
+    assert_eq!(
+        check("10.0.0.66", AclOperation::Read, "pxe/boot.img"),
+        AclAction::Deny
+    );
+    assert_eq!(
+        check("10.0.0.66", AclOperation::Read, "public/readme"),
+        AclAction::Deny
+    );
+    assert_eq!(
+        check("192.0.2.1", AclOperation::Read, "pxe/boot.img"),
+        AclAction::Deny
+    );
+    assert_eq!(
+        check("10.0.0.1", AclOperation::Write, "upload/a.bin"),
+        AclAction::Allow
+    );
+    assert_eq!(
+        check("192.0.2.1", AclOperation::Write, "public/a.bin"),
+        AclAction::Allow
+    );
+}
+
+#[test]
+fn request_matching_no_rule_is_denied() {
+    let acl = Acl::parse("allow read 10.0.0.0/8 pxe/*").unwrap();
+    // another address, operation or path each miss the only rule
+    assert_eq!(
+        acl.check(ip("192.0.2.1"), AclOperation::Read, "pxe/boot.img"),
+        AclAction::Deny
+    );
+    assert_eq!(
+        acl.check(ip("10.0.0.1"), AclOperation::Write, "pxe/boot.img"),
+        AclAction::Deny
+    );
+    assert_eq!(
+        acl.check(ip("10.0.0.1"), AclOperation::Read, "boot.img"),
+        AclAction::Deny
+    );
+
+    let acl = Acl::parse("deny write 0.0.0.0/0 *").unwrap();
+    assert_eq!(
+        acl.check(ip("10.0.0.1"), AclOperation::Read, "pxe/boot.img"),
+        AclAction::Deny
+    );
+}
+
+#[test]
+fn any_covers_both_operations() {
+    let acl = Acl::parse("allow any 10.0.0.0/8 *").unwrap();
+    for operation in [AclOperation::Read, AclOperation::Write] {
+        assert_eq!(acl.check(ip("10.0.0.1"), operation, "a"), AclAction::Allow);
+    }
+}
+
+#[test]
+fn parse_errors_name_their_line() {
+    let error = |contents: &str| Acl::parse(contents).unwrap_err().to_string();
+    assert_eq!(
+        error("# rules\nallow read 10.0.0.0/8 *\npermit read 10.0.0.0/8 *"),
+        "line 3: unknown action permit"
+    );
+    assert_eq!(
+        error("allow delete 10.0.0.0/8 *"),
+        "line 1: unknown operation delete"
+    );
+    assert_eq!(
+        error("\nallow read 10.0.0.0/40 *"),
+        "line 2: invalid prefix length in 10.0.0.0/40"
+    );
+    assert_eq!(
+        error("allow read 10.0.0.0/8"),
+        "line 1: expected `<allow|deny> <read|write|any> <cidr> <path glob>`"
+    );
+    assert_eq!("deny".parse::<AclAction>().unwrap(), AclAction::Deny);
+    assert_eq!("any".parse::<AclOperation>().unwrap(), AclOperation::Any);
+}
@@ -0,0 +1,153 @@
+// Round trips every kind of packet through the encoder and the parser.
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+use tftp_libs::{build_message, extract_message, ErrorCode, FileName, Message, MessageBuf};
+
+fn text() -> impl Strategy<Value = String> {
+    "[^\\x00]{0,32}"
+}
+
+fn options() -> impl Strategy<Value = Vec<(String, String)>> {
+    vec(("[a-zA-Z0-9]{0,12}", text()), 0..4)
+}
+
+fn file_name() -> impl Strategy<Value = Vec<u8>> {
+    vec(1u8..=255, 0..64)
+}
+
+fn message() -> impl Strategy<Value = MessageBuf> {
+    prop_oneof![
+        (file_name(), text(), options()).prop_map(|(file_name, mode, options)| {
+            MessageBuf::ReadRequest {
+                file_name,
+                mode,
+                options,
+            }
+        }),
+        (file_name(), text(), options()).prop_map(|(file_name, mode, options)| {
+            MessageBuf::WriteRequest {
+                file_name,
+                mode,
+                options,
+            }
+        }),
+        (any::<u16>(), vec(any::<u8>(), 0..1500))
+            .prop_map(|(block_number, data)| MessageBuf::Data { block_number, data }),
+        any::<u16>().prop_map(|block_number| MessageBuf::Ack { block_number }),
+        (any::<u16>(), text()).prop_map(|(error_code, error_message)| MessageBuf::Error {
+            error_code: ErrorCode::from(error_code),
+            error_message,
+        }),
+        options().prop_map(|options| MessageBuf::OptionAck { options }),
+    ]
+}
+
+fn encode(message: &MessageBuf) -> Vec<u8> {
+    let mut packet = vec![0; message.encoded_len()];
+    let length = message.encode_into(&mut packet).unwrap();
+    assert_eq!(length, packet.len());
+    packet
+}
+
+proptest! {
+    #[test]
+    fn round_trip(message in message()) {
+        let packet = encode(&message);
+        let parsed = extract_message(&packet).unwrap();
+        prop_assert_eq!(build_message(&parsed).unwrap(), packet.clone());
+        prop_assert_eq!(MessageBuf::from(parsed), message);
+    }
+
+    #[test]
+    fn short_buffer_is_refused(message in message()) {
+        let mut packet = vec![0; message.encoded_len() - 1];
+        prop_assert!(message.encode_into(&mut packet).is_err());
+        prop_assert!(packet.iter().all(|byte| *byte == 0));
+    }
+
+    // Whatever the parser accepts can be sent on as it is.
+    #[test]
+    fn parsed_packets_encode(packet in vec(any::<u8>(), 0..600)) {
+        if let Ok(message) = extract_message(&packet) {
+            let encoded = build_message(&message).unwrap();
+            prop_assert_eq!(extract_message(&encoded).unwrap(), message);
+        }
+    }
+}
+
+#[test]
+fn nul_bytes_are_refused() {
+    let messages = [
+        Message::ReadRequest {
+            file_name: FileName::new(&b"boot\0.img"[..]),
+            mode: "octet",
+            options: Vec::new(),
+        },
+        Message::WriteRequest {
+            file_name: FileName::from("boot.img"),
+            mode: "oct\0et",
+            options: Vec::new(),
+        },
+        Message::OptionAck {
+            options: vec![("blksize", "1468\0")],
+        },
+        Message::Error {
+            error_code: ErrorCode::NotDefined,
+            error_message: "bad\0message",
+        },
+    ];
+    for message in messages {
+        assert!(message.validate().is_err(), "{:?}", message);
+        assert!(build_message(&message).is_err(), "{:?}", message);
+    }
+}
+
+#[test]
+fn data_may_contain_nul_bytes() {
+    let message = Message::Data {
+        block_number: 1,
+        data: &[0, 1, 0],
+    };
+    assert_eq!(build_message(&message).unwrap(), [0, 3, 0, 1, 0, 1, 0]);
+}
+
+// Parsing copies nothing, every field points into the datagram.
+#[test]
+fn fields_borrow_from_the_datagram() {
+    let inside = |packet: &[u8], field: &[u8]| packet.as_ptr_range().contains(&field.as_ptr());
+    let packet = build_message(&Message::ReadRequest {
+        file_name: FileName::from("boot.img"),
+        mode: "octet",
+        options: vec![("BLKSIZE", "1468")],
+    })
+    .unwrap();
+    match extract_message(&packet).unwrap() {
+        Message::ReadRequest {
+            file_name,
+            mode,
+            options,
+        } => {
+            assert!(inside(&packet, file_name.as_bytes()));
+            assert!(inside(&packet, mode.as_bytes()));
+            // names keep their case, the negotiation ignores it
+            assert_eq!(options, [("BLKSIZE", "1468")]);
+            assert!(inside(&packet, options[0].0.as_bytes()));
+            assert!(inside(&packet, options[0].1.as_bytes()));
+        }
+        message => panic!("{:?}", message),
+    }
+
+    let packet = build_message(&Message::Error {
+        error_code: ErrorCode::DiskFull,
+        error_message: "disk full",
+    })
+    .unwrap();
+    match extract_message(&packet).unwrap() {
+        Message::Error { error_message, .. } => {
+            assert_eq!(error_message, "disk full");
+            assert!(inside(&packet, error_message.as_bytes()));
+        }
+        message => panic!("{:?}", message),
+    }
+}
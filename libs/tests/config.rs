@@ -0,0 +1,185 @@
+// Configuration files are checked in full before the server starts, every
+// mistake is reported with the line it is on.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tftp_libs::acl::{AclAction, AclOperation};
+use tftp_libs::config::{parse_address, Config};
+use tftp_libs::server::ServerMode;
+use tftp_libs::FileNameEncoding;
+
+fn error(contents: &str) -> String {
+    Config::parse(contents).unwrap_err().to_string()
+}
+
+#[test]
+fn full_configuration_is_read() {
+    let root = std::env::temp_dir();
+    let contents = format!(
+        r#"
+root = "{root}"
+mode = "read-only"
+create = true
+filename_encoding = "lossy"
+
+[[listener]]
+address = "0.0.0.0"
+
+[[listener]]
+address = "[::1]:6969"
+root = "{root}"
+
+[[acl]]
+action = "allow"
+operation = "read"
+network = "10.0.0.0/8"
+path = "pxe/*"
+
+[[acl]]
+action = "deny"
+network = "0.0.0.0/0"
+
+[options]
+max_blksize = 1024
+max_windowsize = 16
+
+[timeouts]
+timeout = 2
+retries = 3
+session = 30
+"#,
+        root = root.display()
+    );
+    let config = Config::parse(&contents).unwrap();
+    assert_eq!(config.root.as_deref(), Some(root.as_path()));
+    assert_eq!(config.mode, Some(ServerMode::ReadOnly));
+    assert_eq!(config.create, Some(true));
+    assert_eq!(config.file_name_encoding, Some(FileNameEncoding::Lossy));
+    assert_eq!(config.listeners.len(), 2);
+    assert_eq!(config.listeners[0].address, "0.0.0.0:69".parse().unwrap());
+    assert_eq!(config.listeners[0].root, None);
+    assert_eq!(config.listeners[1].address, "[::1]:6969".parse().unwrap());
+    assert_eq!(config.listeners[1].root.as_deref(), Some(root.as_path()));
+    assert_eq!(config.max_block_size, Some(1024));
+    assert_eq!(config.max_window_size, Some(16));
+    assert_eq!(config.timeout, Some(Duration::from_secs(2)));
+    assert_eq!(config.retries, Some(3));
+    assert_eq!(config.session_timeout, Some(Duration::from_secs(30)));
+
+    let acl = config.acl.unwrap();
+    let inside = IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3));
+    let outside = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1));
+    assert_eq!(
+        acl.check(inside, AclOperation::Read, "pxe/boot.img"),
+        AclAction::Allow
+    );
+    // the second rule leaves its operation out, which covers writes too
+    assert_eq!(
+        acl.check(inside, AclOperation::Write, "pxe/boot.img"),
+        AclAction::Deny
+    );
+    assert_eq!(
+        acl.check(outside, AclOperation::Read, "pxe/boot.img"),
+        AclAction::Deny
+    );
+}
+
+#[test]
+fn empty_configuration_leaves_everything_unset() {
+    let config = Config::parse("").unwrap();
+    assert!(config.listeners.is_empty());
+    assert_eq!(config.mode, None);
+    assert!(config.acl.is_none());
+}
+
+#[test]
+fn errors_name_their_line() {
+    assert_eq!(
+        error("create = true\n\nmode = \"bogus\"\n"),
+        "line 3: unknown mode bogus"
+    );
+    assert_eq!(
+        error("filename_encoding = \"latin1\"\n"),
+        "line 1: unknown filename encoding latin1"
+    );
+    assert_eq!(
+        error("[[listener]]\naddress = \"localhost:69\"\n"),
+        "line 2: invalid address localhost:69"
+    );
+    assert_eq!(
+        error("[options]\nmax_blksize = 4\n"),
+        "line 2: max_blksize must be between 8 and 65464"
+    );
+    assert_eq!(
+        error("[options]\nmax_windowsize = 0\n"),
+        "line 2: max_windowsize must be at least 1"
+    );
+    assert_eq!(
+        error("[timeouts]\ntimeout = 0\n"),
+        "line 2: timeout must be between 1 and 255 seconds"
+    );
+    assert_eq!(
+        error("[timeouts]\nsession = 0\n"),
+        "line 2: session timeout must be at least 1 second"
+    );
+    assert_eq!(
+        error("[metrics]\naddress = \"nowhere\"\n"),
+        "line 2: invalid metrics address nowhere"
+    );
+    assert_eq!(
+        error("root = \"/nonexistent/tftp/root\"\n"),
+        "line 1: root /nonexistent/tftp/root is not a directory"
+    );
+}
+
+#[test]
+fn acl_errors_name_their_line() {
+    let rule = |action: &str, operation: &str, network: &str| {
+        format!(
+            "[[acl]]\naction = \"allow\"\nnetwork = \"10.0.0.0/8\"\n\n\
+             [[acl]]\naction = \"{}\"\noperation = \"{}\"\nnetwork = \"{}\"\n",
+            action, operation, network
+        )
+    };
+    assert_eq!(
+        error(&rule("permit", "read", "10.0.0.0/8")),
+        "line 6: unknown action permit"
+    );
+    assert_eq!(
+        error(&rule("deny", "delete", "10.0.0.0/8")),
+        "line 7: unknown operation delete"
+    );
+    assert_eq!(
+        error(&rule("deny", "write", "10.0.0.0/33")),
+        "line 8: invalid prefix length in 10.0.0.0/33"
+    );
+    assert_eq!(
+        error(&rule("deny", "write", "example.com")),
+        "line 8: invalid network address example.com"
+    );
+}
+
+#[test]
+fn syntax_errors_and_unknown_keys_are_refused() {
+    assert!(error("mode = \n").contains("line 1"));
+    assert!(error("root = \"/tmp\"\nport = 69\n").contains("line 2"));
+    assert!(error("[timeouts]\nretry = 5\n").contains("line 2"));
+}
+
+#[test]
+fn addresses_default_their_port() {
+    let v4 = |port| SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+    assert_eq!(parse_address("127.0.0.1", 69), Some(v4(69)));
+    assert_eq!(parse_address("127.0.0.1:6969", 69), Some(v4(6969)));
+    assert_eq!(parse_address("::1", 69), Some("[::1]:69".parse().unwrap()));
+    assert_eq!(
+        parse_address("[::1]", 70),
+        Some("[::1]:70".parse().unwrap())
+    );
+    assert_eq!(
+        parse_address("[::1]:6969", 69),
+        Some("[::1]:6969".parse().unwrap())
+    );
+    assert_eq!(parse_address("localhost", 69), None);
+    assert_eq!(parse_address("127.0.0.1:port", 69), None);
+}
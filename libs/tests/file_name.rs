@@ -0,0 +1,95 @@
+// Requested file names are raw bytes, each encoding policy decides which of
+// them the server accepts.
+
+use tftp_libs::{FileName, FileNameEncoding};
+
+const ASCII: &[u8] = b"pxe/boot.img";
+const UTF8: &[u8] = "pxe/démarrage.img".as_bytes();
+// "démarrage" in Latin-1
+const LATIN1: &[u8] = b"pxe/d\xe9marrage.img";
+
+fn decode(bytes: &[u8], encoding: FileNameEncoding) -> Result<String, String> {
+    FileName::new(bytes)
+        .decode(encoding)
+        .map_err(|error| error.to_string())
+}
+
+#[test]
+fn ascii_accepts_nothing_else() {
+    assert_eq!(
+        decode(ASCII, FileNameEncoding::Ascii),
+        Ok("pxe/boot.img".to_string())
+    );
+    assert_eq!(
+        decode(UTF8, FileNameEncoding::Ascii),
+        Err("file name pxe/démarrage.img is not ASCII".to_string())
+    );
+    assert_eq!(
+        decode(LATIN1, FileNameEncoding::Ascii),
+        Err("file name pxe/d\u{fffd}marrage.img is not ASCII".to_string())
+    );
+}
+
+#[test]
+fn utf8_refuses_invalid_bytes() {
+    assert_eq!(
+        decode(ASCII, FileNameEncoding::Utf8),
+        Ok("pxe/boot.img".to_string())
+    );
+    assert_eq!(
+        decode(UTF8, FileNameEncoding::Utf8),
+        Ok("pxe/démarrage.img".to_string())
+    );
+    assert_eq!(
+        decode(LATIN1, FileNameEncoding::Utf8),
+        Err("file name pxe/d\u{fffd}marrage.img is not valid UTF-8".to_string())
+    );
+}
+
+#[test]
+fn lossy_replaces_invalid_bytes() {
+    assert_eq!(
+        decode(ASCII, FileNameEncoding::Lossy),
+        Ok("pxe/boot.img".to_string())
+    );
+    assert_eq!(
+        decode(UTF8, FileNameEncoding::Lossy),
+        Ok("pxe/démarrage.img".to_string())
+    );
+    assert_eq!(
+        decode(LATIN1, FileNameEncoding::Lossy),
+        Ok("pxe/d\u{fffd}marrage.img".to_string())
+    );
+}
+
+#[test]
+fn default_is_utf8() {
+    assert_eq!(FileNameEncoding::default(), FileNameEncoding::Utf8);
+}
+
+#[test]
+fn encodings_parse_their_names() {
+    for encoding in [
+        FileNameEncoding::Ascii,
+        FileNameEncoding::Utf8,
+        FileNameEncoding::Lossy,
+    ] {
+        assert_eq!(FileNameEncoding::parse(encoding.as_str()), Some(encoding));
+    }
+    assert_eq!(
+        FileNameEncoding::parse("UTF8"),
+        Some(FileNameEncoding::Utf8)
+    );
+    assert_eq!(FileNameEncoding::parse("latin1"), None);
+}
+
+// Names are logged as sent, without letting the peer forge log lines.
+#[test]
+fn display_escapes_control_characters() {
+    assert_eq!(FileName::new(b"a\nb\x1b[0m").to_string(), "a\\nb\\u{1b}[0m");
+    assert_eq!(
+        FileName::new(LATIN1).to_string(),
+        "pxe/d\u{fffd}marrage.img"
+    );
+    assert_eq!(FileName::from("pxe/boot.img").as_bytes(), ASCII);
+}
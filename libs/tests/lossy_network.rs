@@ -0,0 +1,119 @@
+// Moves files between a server and a client over loopback, through a proxy
+// that mistreats the packets, and checks they arrive byte for byte.
+
+mod support;
+
+use std::fs;
+use std::io::Cursor;
+use std::time::Duration;
+use support::{contents, Direction, Fault, Harness, Rule, SharedBuffer};
+use tftp_libs::OpCode;
+
+// plain lock step transfers and windowed ones, each ending in a short block
+const TRANSFERS: [(usize, u16, usize); 3] = [(512, 1, 9000), (1024, 4, 20000), (512, 8, 16384)];
+
+fn check_transfers(name: &str, rules: Vec<Rule>) {
+    let mistreated = !rules.is_empty();
+    let harness = Harness::start(name, rules);
+    for (block_size, window_size, size) in TRANSFERS {
+        let expected = contents(size);
+        let client = harness
+            .client()
+            .block_size(Some(block_size))
+            .window_size(Some(window_size))
+            .build()
+            .unwrap();
+        let file_name = format!("{}x{}.bin", block_size, window_size);
+
+        fs::write(harness.root().join(&file_name), &expected).unwrap();
+        let output = SharedBuffer::default();
+        let received = client.get(&file_name, output.clone()).unwrap();
+        assert_eq!(received, size as u64, "get {}", file_name);
+        assert!(output.contents() == expected, "get {} corrupted", file_name);
+
+        let uploaded = format!("up_{}", file_name);
+        let sent = client
+            .put(Cursor::new(expected.clone()), &uploaded)
+            .unwrap();
+        assert_eq!(sent, size as u64, "put {}", uploaded);
+        let stored = fs::read(harness.root().join(&uploaded)).unwrap();
+        assert!(stored == expected, "put {} corrupted", uploaded);
+    }
+    if mistreated {
+        assert!(harness.proxy().faults() > 0, "no packet was mistreated");
+    }
+}
+
+fn dropped() -> Vec<Rule> {
+    vec![
+        Rule::new(Fault::Drop).opcode(OpCode::Data).one_in(7),
+        Rule::new(Fault::Drop).opcode(OpCode::Ack).one_in(5),
+    ]
+}
+
+fn duplicated() -> Vec<Rule> {
+    vec![Rule::new(Fault::Duplicate).one_in(3)]
+}
+
+// the longer delay outlasts the timeout, the packet goes out again before
+// the late one arrives
+fn delayed() -> Vec<Rule> {
+    vec![
+        Rule::new(Fault::Delay(support::TIMEOUT * 2)).one_in(11),
+        Rule::new(Fault::Delay(Duration::from_millis(40))).one_in(4),
+    ]
+}
+
+fn reordered() -> Vec<Rule> {
+    vec![
+        Rule::new(Fault::Reorder)
+            .direction(Direction::ToClient)
+            .one_in(3),
+        Rule::new(Fault::Reorder)
+            .direction(Direction::ToServer)
+            .one_in(5),
+    ]
+}
+
+#[test]
+fn clean_network() {
+    check_transfers("clean", Vec::new());
+}
+
+#[test]
+fn dropped_packets() {
+    check_transfers("dropped", dropped());
+}
+
+#[test]
+fn duplicated_packets() {
+    check_transfers("duplicated", duplicated());
+}
+
+#[test]
+fn delayed_packets() {
+    check_transfers("delayed", delayed());
+}
+
+#[test]
+fn reordered_packets() {
+    check_transfers("reordered", reordered());
+}
+
+#[test]
+fn everything_at_once() {
+    let mut rules = dropped();
+    rules.extend(reordered());
+    rules.extend(duplicated());
+    rules.extend(delayed());
+    check_transfers("everything", rules);
+}
+
+// The reply to a request going missing makes the client ask again.
+#[test]
+fn lost_first_reply() {
+    let rules = vec![Rule::new(Fault::Drop)
+        .direction(Direction::ToClient)
+        .limit(1)];
+    check_transfers("first_reply", rules);
+}
@@ -0,0 +1,381 @@
+// Feeds datagrams to a server that is not running, reading its replies from
+// the socket of the peer they pretend to come from.
+
+use std::fs;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tftp_libs::acl::Acl;
+use tftp_libs::server::{TftpServer, TftpServerBuilder};
+use tftp_libs::{
+    build_message, extract_message, ErrorCode, FileName, FileNameEncoding, Message, MessageBuf,
+};
+
+// A scratch directory for the server to serve, removed afterwards.
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Root {
+        let path =
+            std::env::temp_dir().join(format!("tftp_server_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Root(path)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn builder(root: &Root) -> TftpServerBuilder {
+    TftpServer::builder()
+        .address("127.0.0.1:0")
+        .root(root.path())
+        .create(true)
+}
+
+struct Peer {
+    socket: UdpSocket,
+    address: SocketAddr,
+}
+
+impl Peer {
+    fn new() -> Peer {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let address = socket.local_addr().unwrap();
+        Peer { socket, address }
+    }
+
+    // Hands `message` to the server and returns its reply.
+    fn send(&self, server: &mut TftpServer, message: Message) -> MessageBuf {
+        server.handle_datagram(self.address, &build_message(&message).unwrap());
+        let mut buffer = [0; 1024];
+        let (length, _) = self.socket.recv_from(&mut buffer).unwrap();
+        MessageBuf::from(extract_message(&buffer[..length]).unwrap())
+    }
+}
+
+fn read_request(file_name: &str) -> Message<'_> {
+    Message::ReadRequest {
+        file_name: FileName::from(file_name),
+        mode: "octet",
+        options: Vec::new(),
+    }
+}
+
+fn write_request(file_name: &str) -> Message<'_> {
+    Message::WriteRequest {
+        file_name: FileName::from(file_name),
+        mode: "octet",
+        options: Vec::new(),
+    }
+}
+
+// A client whose final ACK got lost sends its last block again, the server
+// must acknowledge it rather than call it a stranger.
+#[test]
+fn last_block_of_finished_upload_is_acknowledged_again() {
+    let root = Root::new("last_block");
+    let mut server = builder(&root).build().unwrap();
+    let peer = Peer::new();
+    let last_block = Message::Data {
+        block_number: 1,
+        data: b"abc",
+    };
+
+    let reply = peer.send(&mut server, write_request("upload.bin"));
+    assert_eq!(reply, MessageBuf::Ack { block_number: 0 });
+    let reply = peer.send(&mut server, last_block.clone());
+    assert_eq!(reply, MessageBuf::Ack { block_number: 1 });
+    let reply = peer.send(&mut server, last_block.clone());
+    assert_eq!(reply, MessageBuf::Ack { block_number: 1 });
+    assert_eq!(fs::read(root.path().join("upload.bin")).unwrap(), b"abc");
+
+    let stranger = Peer::new();
+    let reply = stranger.send(&mut server, last_block);
+    assert!(
+        matches!(
+            reply,
+            MessageBuf::Error {
+                error_code: ErrorCode::UnknownTransferId,
+                ..
+            }
+        ),
+        "{:?}",
+        reply
+    );
+}
+
+// The ACL sees the same path as the file lookup, however the name is spelled.
+#[test]
+fn acl_checks_the_normalised_path() {
+    let root = Root::new("acl_deny");
+    fs::create_dir(root.path().join("secret")).unwrap();
+    fs::write(root.path().join("secret/key"), b"key").unwrap();
+    let acl = Acl::parse("deny read 0.0.0.0/0 secret/*\nallow read 0.0.0.0/0 *").unwrap();
+    let mut server = builder(&root).acl(acl).build().unwrap();
+
+    for file_name in [
+        "secret/key",
+        "/secret/key",
+        "./secret/key",
+        "secret/./key",
+        "//secret//key",
+        "secret/../secret/key",
+    ] {
+        let reply = Peer::new().send(&mut server, read_request(file_name));
+        assert!(
+            matches!(
+                reply,
+                MessageBuf::Error {
+                    error_code: ErrorCode::AccessViolation,
+                    ..
+                }
+            ),
+            "{}: {:?}",
+            file_name,
+            reply
+        );
+    }
+}
+
+// PXE clients ask for names with a leading slash, which an allow list
+// written relative to the root must let through.
+#[test]
+fn acl_allows_leading_slash() {
+    let root = Root::new("acl_allow");
+    fs::create_dir(root.path().join("pxe")).unwrap();
+    fs::write(root.path().join("pxe/boot.img"), b"boot").unwrap();
+    let acl = Acl::parse("allow read 0.0.0.0/0 pxe/*").unwrap();
+    let mut server = builder(&root).acl(acl).build().unwrap();
+
+    let reply = Peer::new().send(&mut server, read_request("/pxe/boot.img"));
+    let expected = MessageBuf::Data {
+        block_number: 1,
+        data: b"boot".to_vec(),
+    };
+    assert_eq!(reply, expected);
+}
+
+// An upload announcing more than any transfer could carry does not get its
+// tsize acknowledged.
+#[test]
+fn absurd_tsize_is_ignored() {
+    let root = Root::new("tsize");
+    let mut server = builder(&root).build().unwrap();
+    let size = u64::MAX.to_string();
+    let request = Message::WriteRequest {
+        file_name: FileName::from("upload.bin"),
+        mode: "octet",
+        options: vec![("tsize", size.as_str())],
+    };
+    let reply = Peer::new().send(&mut server, request);
+    assert_eq!(reply, MessageBuf::Ack { block_number: 0 });
+}
+
+// An upload that fails half way leaves nothing behind, whichever side ends it.
+#[test]
+fn failed_upload_is_removed() {
+    let root = Root::new("failed_upload");
+    let mut server = builder(&root).build().unwrap();
+    let first_block = Message::Data {
+        block_number: 1,
+        data: &[7; 512],
+    };
+
+    let peer = Peer::new();
+    let reply = peer.send(&mut server, write_request("aborted.bin"));
+    assert_eq!(reply, MessageBuf::Ack { block_number: 0 });
+    let reply = peer.send(&mut server, first_block.clone());
+    assert_eq!(reply, MessageBuf::Ack { block_number: 1 });
+    let error = Message::Error {
+        error_code: ErrorCode::NotDefined,
+        error_message: "cancelled",
+    };
+    server.handle_datagram(peer.address, &build_message(&error).unwrap());
+    assert!(!root.path().join("aborted.bin").exists());
+
+    let peer = Peer::new();
+    let reply = peer.send(&mut server, write_request("illegal.bin"));
+    assert_eq!(reply, MessageBuf::Ack { block_number: 0 });
+    let reply = peer.send(&mut server, first_block);
+    assert_eq!(reply, MessageBuf::Ack { block_number: 1 });
+    let reply = peer.send(&mut server, Message::Ack { block_number: 1 });
+    assert!(matches!(reply, MessageBuf::Error { .. }), "{:?}", reply);
+    assert!(!root.path().join("illegal.bin").exists());
+    assert_eq!(fs::read_dir(root.path()).unwrap().count(), 0);
+}
+
+// Without `create` uploads can only replace files, an aborted one must leave
+// the file as it was for the next attempt.
+#[test]
+fn aborted_upload_keeps_the_existing_file() {
+    let root = Root::new("keep_existing");
+    fs::write(root.path().join("keep.bin"), b"original").unwrap();
+    let mut server = builder(&root).create(false).build().unwrap();
+    let first_block = Message::Data {
+        block_number: 1,
+        data: &[7; 512],
+    };
+
+    let peer = Peer::new();
+    let reply = peer.send(&mut server, write_request("keep.bin"));
+    assert_eq!(reply, MessageBuf::Ack { block_number: 0 });
+    let reply = peer.send(&mut server, first_block.clone());
+    assert_eq!(reply, MessageBuf::Ack { block_number: 1 });
+    let error = Message::Error {
+        error_code: ErrorCode::NotDefined,
+        error_message: "cancelled",
+    };
+    server.handle_datagram(peer.address, &build_message(&error).unwrap());
+    assert_eq!(fs::read(root.path().join("keep.bin")).unwrap(), b"original");
+
+    let peer = Peer::new();
+    let reply = peer.send(&mut server, write_request("keep.bin"));
+    assert_eq!(reply, MessageBuf::Ack { block_number: 0 });
+    let reply = peer.send(&mut server, first_block);
+    assert_eq!(reply, MessageBuf::Ack { block_number: 1 });
+    let last_block = Message::Data {
+        block_number: 2,
+        data: b"end",
+    };
+    let reply = peer.send(&mut server, last_block);
+    assert_eq!(reply, MessageBuf::Ack { block_number: 2 });
+    let mut expected = vec![7; 512];
+    expected.extend_from_slice(b"end");
+    assert_eq!(fs::read(root.path().join("keep.bin")).unwrap(), expected);
+    let names: Vec<_> = fs::read_dir(root.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["keep.bin"]);
+}
+
+fn error_code(reply: &MessageBuf) -> Option<ErrorCode> {
+    match reply {
+        MessageBuf::Error { error_code, .. } => Some(*error_code),
+        _ => None,
+    }
+}
+
+// However a name is spelled, it has to stay below the root and read as a
+// file name.
+#[test]
+fn file_names_escaping_the_root_are_refused() {
+    let root = Root::new("escaping_names");
+    fs::create_dir(root.path().join("pxe")).unwrap();
+    fs::write(root.path().join("pxe/boot.img"), b"boot").unwrap();
+    fs::write(root.path().join("pxe/a\nb"), b"newline").unwrap();
+    let mut server = builder(&root).build().unwrap();
+
+    for file_name in [
+        "",
+        ".",
+        "/",
+        "./",
+        "..",
+        "../boot.img",
+        "pxe/../pxe/boot.img",
+        "pxe/../../etc/passwd",
+        "/../etc/passwd",
+        "pxe/a\nb",
+        "pxe/boot.img\r",
+        "pxe/\u{1b}boot.img",
+        "pxe/boot\u{7f}.img",
+    ] {
+        for request in [read_request(file_name), write_request(file_name)] {
+            let reply = Peer::new().send(&mut server, request);
+            assert_eq!(
+                error_code(&reply),
+                Some(ErrorCode::AccessViolation),
+                "{:?}: {:?}",
+                file_name,
+                reply
+            );
+        }
+    }
+    assert_eq!(fs::read(root.path().join("pxe/a\nb")).unwrap(), b"newline");
+}
+
+#[test]
+fn file_names_are_normalised() {
+    let root = Root::new("normalised_names");
+    fs::create_dir(root.path().join("pxe")).unwrap();
+    fs::write(root.path().join("pxe/boot.img"), b"boot").unwrap();
+    let mut server = builder(&root).build().unwrap();
+    let expected = MessageBuf::Data {
+        block_number: 1,
+        data: b"boot".to_vec(),
+    };
+
+    for file_name in [
+        "pxe/boot.img",
+        "/pxe/boot.img",
+        "./pxe/./boot.img",
+        "//pxe//boot.img",
+        "pxe/boot.img/.",
+    ] {
+        let reply = Peer::new().send(&mut server, read_request(file_name));
+        assert_eq!(reply, expected, "{:?}", file_name);
+    }
+}
+
+// Each policy on a plain name, a UTF-8 one and one in Latin-1.
+#[test]
+fn file_name_encodings() {
+    let root = Root::new("encodings");
+    fs::write(root.path().join("boot.img"), b"ascii").unwrap();
+    fs::write(root.path().join("démarrage.img"), b"utf-8").unwrap();
+    fs::write(root.path().join("d\u{fffd}marrage.img"), b"lossy").unwrap();
+    let names: [&[u8]; 3] = [b"boot.img", "démarrage.img".as_bytes(), b"d\xe9marrage.img"];
+    let refused = MessageBuf::Error {
+        error_code: ErrorCode::IllegalOperation,
+        error_message: String::new(),
+    };
+    let served = |data: &[u8]| MessageBuf::Data {
+        block_number: 1,
+        data: data.to_vec(),
+    };
+
+    for (encoding, expected) in [
+        (
+            FileNameEncoding::Ascii,
+            [served(b"ascii"), refused.clone(), refused.clone()],
+        ),
+        (
+            FileNameEncoding::Utf8,
+            [served(b"ascii"), served(b"utf-8"), refused.clone()],
+        ),
+        (
+            FileNameEncoding::Lossy,
+            [served(b"ascii"), served(b"utf-8"), served(b"lossy")],
+        ),
+    ] {
+        let mut server = builder(&root).file_name_encoding(encoding).build().unwrap();
+        for (name, expected) in names.iter().zip(expected) {
+            let request = Message::ReadRequest {
+                file_name: FileName::new(name),
+                mode: "octet",
+                options: Vec::new(),
+            };
+            let reply = match Peer::new().send(&mut server, request) {
+                MessageBuf::Error { error_code, .. } => MessageBuf::Error {
+                    error_code,
+                    error_message: String::new(),
+                },
+                reply => reply,
+            };
+            assert_eq!(reply, expected, "{:?} {:?}", encoding, name);
+        }
+    }
+}
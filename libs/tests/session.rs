@@ -0,0 +1,311 @@
+// Drives the protocol core of a transfer by hand, no sockets involved.
+
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tftp_libs::transfer::{Progress, SessionState, TransferProgress};
+use tftp_libs::{
+    build_message, extract_message, ErrorCode, FileName, Message, MessageBuf, TftpSessionInfo,
+};
+
+fn options() -> Vec<(&'static str, &'static str)> {
+    vec![("blksize", "1024")]
+}
+
+fn packet(message: Message) -> Vec<u8> {
+    build_message(&message).unwrap()
+}
+
+// Everything the session wants to send right now.
+fn transmitted(session_info: &mut TftpSessionInfo) -> Vec<Vec<u8>> {
+    let mut packets = Vec::new();
+    while let Some(packet) = session_info.poll_transmit() {
+        packets.push(packet.to_vec());
+    }
+    packets
+}
+
+// Sets a client session up for a transfer and returns the request starting
+// it.
+fn request(
+    session_info: &mut TftpSessionInfo,
+    upload: bool,
+    options: Vec<(&'static str, &'static str)>,
+) -> Message<'static> {
+    let file_name = FileName::from("file.bin");
+    let mode = "octet";
+    if upload {
+        session_info.reader = Some(Box::new(Cursor::new(vec![7; 3000])));
+        Message::WriteRequest {
+            file_name,
+            mode,
+            options,
+        }
+    } else {
+        session_info.writer = Some(Box::new(Vec::new()));
+        Message::ReadRequest {
+            file_name,
+            mode,
+            options,
+        }
+    }
+}
+
+// A client that sent a request with options and got them acknowledged.
+fn negotiated(upload: bool) -> TftpSessionInfo {
+    let now = Instant::now();
+    let mut session_info = TftpSessionInfo::new();
+    let request = request(&mut session_info, upload, options());
+    session_info.send_packet(now, 0, request).unwrap();
+    transmitted(&mut session_info);
+    let oack = Message::OptionAck { options: options() };
+    assert!(matches!(
+        session_info.handle_packet(now, oack),
+        Ok(Progress::Continue)
+    ));
+    session_info
+}
+
+// The server resends its OACK when ACK 0 got lost, which goes out again.
+#[test]
+fn repeated_oack_resends_ack_0() {
+    let mut session_info = negotiated(false);
+    let ack = packet(Message::Ack { block_number: 0 });
+    assert_eq!(transmitted(&mut session_info), [ack.as_slice()]);
+
+    let oack = Message::OptionAck { options: options() };
+    let result = session_info.handle_packet(Instant::now(), oack);
+    assert!(
+        matches!(result, Ok(Progress::Ignored)),
+        "{:?}",
+        result.err()
+    );
+    assert_eq!(transmitted(&mut session_info), [ack.as_slice()]);
+}
+
+// Same for an upload that missed its first block.
+#[test]
+fn repeated_oack_resends_first_block() {
+    let mut session_info = negotiated(true);
+    let sent = transmitted(&mut session_info);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0][..4], [0, 3, 0, 1]);
+
+    let oack = Message::OptionAck { options: options() };
+    let result = session_info.handle_packet(Instant::now(), oack);
+    assert!(
+        matches!(result, Ok(Progress::Ignored)),
+        "{:?}",
+        result.err()
+    );
+    assert_eq!(transmitted(&mut session_info), sent);
+}
+
+// Once the transfer moved on, a late OACK is a duplicate and nothing more.
+#[test]
+fn late_oack_is_ignored() {
+    let mut session_info = negotiated(false);
+    transmitted(&mut session_info);
+    let data = Message::Data {
+        block_number: 1,
+        data: &[1; 1024],
+    };
+    assert!(session_info.handle_packet(Instant::now(), data).is_ok());
+    transmitted(&mut session_info);
+
+    let oack = Message::OptionAck { options: options() };
+    let result = session_info.handle_packet(Instant::now(), oack);
+    assert!(
+        matches!(result, Ok(Progress::Ignored)),
+        "{:?}",
+        result.err()
+    );
+    assert!(transmitted(&mut session_info).is_empty());
+}
+
+// The peer picks the total with the tsize option, a huge one must not
+// overflow the estimate.
+#[test]
+fn eta_of_a_huge_total() {
+    let progress = TransferProgress {
+        peer: SocketAddr::from(([127, 0, 0, 1], 69)),
+        file_name: "file.bin",
+        bytes: 8,
+        total: Some(u64::MAX),
+        elapsed: Duration::from_secs(60),
+    };
+    assert_eq!(progress.eta(), None);
+    let progress = TransferProgress {
+        total: Some(16),
+        ..progress
+    };
+    assert_eq!(progress.eta(), Some(Duration::from_secs(60)));
+}
+
+#[test]
+fn absurd_tsize_is_refused() {
+    let now = Instant::now();
+    let mut session_info = TftpSessionInfo::new();
+    session_info.writer = Some(Box::new(Vec::new()));
+    let request = Message::ReadRequest {
+        file_name: FileName::from("file.bin"),
+        mode: "octet",
+        options: vec![("tsize", "0")],
+    };
+    session_info.send_packet(now, 0, request).unwrap();
+    transmitted(&mut session_info);
+
+    let size = u64::MAX.to_string();
+    let oack = Message::OptionAck {
+        options: vec![("tsize", size.as_str())],
+    };
+    assert!(session_info.handle_packet(now, oack).is_err());
+    assert_eq!(session_info.total, None);
+    let sent = transmitted(&mut session_info);
+    assert_eq!(sent.len(), 1);
+    let reply = MessageBuf::from(extract_message(&sent[0]).unwrap());
+    assert!(
+        matches!(
+            reply,
+            MessageBuf::Error {
+                error_code: ErrorCode::OptionNegotiation,
+                ..
+            }
+        ),
+        "{:?}",
+        reply
+    );
+}
+
+// A block size under the minimum, 0 above all, would never end a download.
+#[test]
+fn tiny_blksize_is_refused() {
+    for block_size in ["0", "7"] {
+        let now = Instant::now();
+        let mut session_info = TftpSessionInfo::new();
+        let request = request(&mut session_info, false, options());
+        session_info.send_packet(now, 0, request).unwrap();
+        transmitted(&mut session_info);
+
+        let oack = Message::OptionAck {
+            options: vec![("blksize", block_size)],
+        };
+        assert!(session_info.handle_packet(now, oack).is_err());
+        let sent = transmitted(&mut session_info);
+        assert_eq!(sent.len(), 1);
+        let reply = MessageBuf::from(extract_message(&sent[0]).unwrap());
+        assert!(
+            matches!(
+                reply,
+                MessageBuf::Error {
+                    error_code: ErrorCode::OptionNegotiation,
+                    ..
+                }
+            ),
+            "{}: {:?}",
+            block_size,
+            reply
+        );
+    }
+}
+
+// Which packets from the peer each state lets through.
+#[test]
+fn states_accept_the_packets_of_their_direction() {
+    let oack = Message::OptionAck { options: options() };
+    let data = Message::Data {
+        block_number: 1,
+        data: b"abc",
+    };
+    let ack = Message::Ack { block_number: 0 };
+    let error = Message::Error {
+        error_code: ErrorCode::NotDefined,
+        error_message: "stop",
+    };
+    let request = Message::ReadRequest {
+        file_name: FileName::from("file.bin"),
+        mode: "octet",
+        options: Vec::new(),
+    };
+    let cases = [
+        (
+            SessionState::AwaitingOack { sending: false },
+            [true, true, false, true],
+        ),
+        (
+            SessionState::AwaitingOack { sending: true },
+            [true, false, true, true],
+        ),
+        (SessionState::Receiving(0), [false, true, false, true]),
+        (SessionState::Sending(1), [false, false, true, true]),
+        (SessionState::Finishing, [false, false, true, true]),
+        (SessionState::Done, [false, false, false, false]),
+        (SessionState::Failed, [false, false, false, false]),
+    ];
+    for (state, expected) in cases {
+        for (message, accepted) in [&oack, &data, &ack, &error].into_iter().zip(expected) {
+            assert_eq!(
+                state.accepts(message).is_ok(),
+                accepted,
+                "{:?} with {:?}",
+                state,
+                message
+            );
+        }
+        assert!(state.accepts(&request).is_err(), "{:?}", state);
+    }
+}
+
+// A request moves the session to the awaiting state of its direction.
+#[test]
+fn request_awaits_its_direction() {
+    for upload in [false, true] {
+        let mut session_info = TftpSessionInfo::new();
+        let request = request(&mut session_info, upload, Vec::new());
+        session_info
+            .send_packet(Instant::now(), 0, request)
+            .unwrap();
+        assert_eq!(
+            session_info.state,
+            SessionState::AwaitingOack { sending: upload }
+        );
+    }
+}
+
+// A download answered with an ACK, or an upload with DATA, is an illegal
+// operation rather than the start of the transfer.
+#[test]
+fn wrong_direction_answer_is_refused() {
+    let now = Instant::now();
+    for upload in [false, true] {
+        let mut session_info = TftpSessionInfo::new();
+        let request = request(&mut session_info, upload, Vec::new());
+        session_info.send_packet(now, 0, request).unwrap();
+        transmitted(&mut session_info);
+
+        let answer = if upload {
+            Message::Data {
+                block_number: 1,
+                data: b"abc",
+            }
+        } else {
+            Message::Ack { block_number: 0 }
+        };
+        assert!(session_info.handle_packet(now, answer).is_err());
+        assert_eq!(session_info.state, SessionState::Failed);
+        let sent = transmitted(&mut session_info);
+        assert_eq!(sent.len(), 1);
+        let reply = MessageBuf::from(extract_message(&sent[0]).unwrap());
+        assert!(
+            matches!(
+                reply,
+                MessageBuf::Error {
+                    error_code: ErrorCode::IllegalOperation,
+                    ..
+                }
+            ),
+            "{:?}",
+            reply
+        );
+    }
+}
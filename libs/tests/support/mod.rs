@@ -0,0 +1,415 @@
+// Runs a server and hands out clients that reach it through an in-process
+// UDP proxy, which drops, duplicates, delays or reorders the packets picked
+// out by its rules.
+
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tftp_libs::client::{TftpClient, TftpClientBuilder};
+use tftp_libs::server::{ServerHandle, TftpServer};
+use tftp_libs::OpCode;
+
+// short for lost packets to be resent quickly
+pub const TIMEOUT: Duration = Duration::from_millis(300);
+const RETRIES: u32 = 10;
+// how long a packet held back for reordering waits for one to overtake it
+const REORDER_HOLD: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ToServer,
+    ToClient,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    Drop,
+    Duplicate,
+    Delay(Duration),
+    // holds the packet back until the next one in the same direction went
+    // through
+    Reorder,
+}
+
+// Applies a fault to one in `one_in` of the packets that match the filters,
+// at most `limit` times. The packets are picked at random, every nth one
+// would keep hitting the same block as a window is resent.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    fault: Fault,
+    direction: Option<Direction>,
+    opcode: Option<OpCode>,
+    one_in: u32,
+    limit: Option<u64>,
+    applied: u64,
+}
+
+impl Rule {
+    pub fn new(fault: Fault) -> Self {
+        Rule {
+            fault,
+            direction: None,
+            opcode: None,
+            one_in: 1,
+            limit: None,
+            applied: 0,
+        }
+    }
+
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = Some(direction);
+        self
+    }
+
+    pub fn opcode(mut self, opcode: OpCode) -> Self {
+        self.opcode = Some(opcode);
+        self
+    }
+
+    pub fn one_in(mut self, one_in: u32) -> Self {
+        self.one_in = one_in.max(1);
+        self
+    }
+
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    fn applies(&mut self, direction: Direction, packet: &[u8], random: &mut Random) -> bool {
+        if self.direction.is_some_and(|wanted| wanted != direction) {
+            return false;
+        }
+        if let Some(opcode) = self.opcode {
+            if packet.get(..2) != Some(&(opcode as u16).to_be_bytes()[..]) {
+                return false;
+            }
+        }
+        if !random.one_in(self.one_in) || self.limit.is_some_and(|limit| self.applied >= limit) {
+            return false;
+        }
+        self.applied += 1;
+        true
+    }
+}
+
+// Seeded the same every time, runs only differ by their timing.
+struct Random(u32);
+
+impl Random {
+    fn one_in(&mut self, n: u32) -> bool {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0.is_multiple_of(n)
+    }
+}
+
+// Forwards datagrams between clients and the server, through a socket of its
+// own for each client so that the server tells them apart like it would
+// without the proxy. Clients see the proxy as the server.
+pub struct Proxy {
+    address: SocketAddr,
+    faults: Arc<AtomicUsize>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Proxy {
+    pub fn start(server: SocketAddr, rules: Vec<Rule>) -> io::Result<Proxy> {
+        let front = UdpSocket::bind("127.0.0.1:0")?;
+        front.set_nonblocking(true)?;
+        let address = front.local_addr()?;
+        let faults = Arc::new(AtomicUsize::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut network = Network {
+            front,
+            server,
+            routes: Vec::new(),
+            rules,
+            random: Random(0x2545_f491),
+            delayed: Vec::new(),
+            held: [None, None],
+            faults: faults.clone(),
+        };
+        let stopped = stop.clone();
+        let thread = thread::spawn(move || network.run(&stopped));
+        Ok(Proxy {
+            address,
+            faults,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    // How many packets the rules have picked out so far.
+    pub fn faults(&self) -> usize {
+        self.faults.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// A packet on its way, with the client at one end of its trip.
+struct Packet {
+    direction: Direction,
+    client: SocketAddr,
+    bytes: Vec<u8>,
+}
+
+struct Network {
+    front: UdpSocket,
+    server: SocketAddr,
+    // each client and the socket that talks to the server for it
+    routes: Vec<(SocketAddr, UdpSocket)>,
+    rules: Vec<Rule>,
+    random: Random,
+    delayed: Vec<(Instant, Packet)>,
+    // a packet held back for reordering in each direction
+    held: [Option<(Instant, Packet)>; 2],
+    faults: Arc<AtomicUsize>,
+}
+
+impl Network {
+    fn run(&mut self, stop: &AtomicBool) {
+        let mut buffer = vec![0; 65536];
+        while !stop.load(Ordering::Relaxed) {
+            let mut busy = false;
+            if let Ok((length, client)) = self.front.recv_from(&mut buffer) {
+                if self.route(client).is_some() {
+                    self.forward(Packet {
+                        direction: Direction::ToServer,
+                        client,
+                        bytes: buffer[..length].to_vec(),
+                    });
+                }
+                busy = true;
+            }
+            let mut index = 0;
+            while index < self.routes.len() {
+                let (client, socket) = &self.routes[index];
+                match socket.recv_from(&mut buffer) {
+                    Ok((length, source)) if source == self.server => {
+                        let packet = Packet {
+                            direction: Direction::ToClient,
+                            client: *client,
+                            bytes: buffer[..length].to_vec(),
+                        };
+                        self.forward(packet);
+                        busy = true;
+                    }
+                    Ok(_) => busy = true,
+                    Err(error) if error.kind() == ErrorKind::WouldBlock => index += 1,
+                    Err(_) => index += 1,
+                }
+            }
+            self.release(Instant::now());
+            if !busy {
+                thread::sleep(Duration::from_micros(200));
+            }
+        }
+    }
+
+    // The socket that talks to the server for `client`, bound on its first
+    // packet.
+    fn route(&mut self, client: SocketAddr) -> Option<&UdpSocket> {
+        if !self.routes.iter().any(|(known, _)| *known == client) {
+            let socket = UdpSocket::bind("127.0.0.1:0").ok()?;
+            socket.set_nonblocking(true).ok()?;
+            self.routes.push((client, socket));
+        }
+        self.routes
+            .iter()
+            .find(|(known, _)| *known == client)
+            .map(|(_, socket)| socket)
+    }
+
+    fn forward(&mut self, packet: Packet) {
+        let direction = packet.direction;
+        let random = &mut self.random;
+        let fault = self.rules.iter_mut().find_map(|rule| {
+            rule.applies(direction, &packet.bytes, random)
+                .then_some(rule.fault)
+        });
+        if fault.is_some() {
+            self.faults.fetch_add(1, Ordering::Relaxed);
+        }
+        match fault {
+            None => {
+                self.send(&packet);
+                self.release_held(direction);
+            }
+            Some(Fault::Drop) => {}
+            Some(Fault::Duplicate) => {
+                self.send(&packet);
+                self.send(&packet);
+                self.release_held(direction);
+            }
+            Some(Fault::Delay(delay)) => self.delayed.push((Instant::now() + delay, packet)),
+            Some(Fault::Reorder) if self.held[direction as usize].is_some() => {
+                self.send(&packet);
+                self.release_held(direction);
+            }
+            Some(Fault::Reorder) => {
+                self.held[direction as usize] = Some((Instant::now() + REORDER_HOLD, packet))
+            }
+        }
+    }
+
+    // Sends the delayed packets that are due and the held ones that waited
+    // long enough.
+    fn release(&mut self, now: Instant) {
+        let mut index = 0;
+        while index < self.delayed.len() {
+            if self.delayed[index].0 <= now {
+                let (_, packet) = self.delayed.remove(index);
+                self.send(&packet);
+            } else {
+                index += 1;
+            }
+        }
+        for direction in [Direction::ToServer, Direction::ToClient] {
+            if self.held[direction as usize]
+                .as_ref()
+                .is_some_and(|(until, _)| *until <= now)
+            {
+                self.release_held(direction);
+            }
+        }
+    }
+
+    fn release_held(&mut self, direction: Direction) {
+        if let Some((_, packet)) = self.held[direction as usize].take() {
+            self.send(&packet);
+        }
+    }
+
+    fn send(&self, packet: &Packet) {
+        let _ = match packet.direction {
+            Direction::ToServer => match self
+                .routes
+                .iter()
+                .find(|(known, _)| *known == packet.client)
+            {
+                Some((_, socket)) => socket.send_to(&packet.bytes, self.server),
+                None => return,
+            },
+            Direction::ToClient => self.front.send_to(&packet.bytes, packet.client),
+        };
+    }
+}
+
+// A server on loopback serving a scratch directory, reached through a proxy.
+pub struct Harness {
+    root: PathBuf,
+    proxy: Proxy,
+    handle: ServerHandle,
+    serving: Option<JoinHandle<()>>,
+}
+
+impl Harness {
+    pub fn start(name: &str, rules: Vec<Rule>) -> Harness {
+        let root = std::env::temp_dir().join(format!("tftp_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).expect("failed to create the server root");
+        let mut server = TftpServer::builder()
+            .address("127.0.0.1:0")
+            .root(&root)
+            .create(true)
+            .timeout(TIMEOUT)
+            .retries(RETRIES)
+            .build()
+            .expect("failed to start the server");
+        let proxy =
+            Proxy::start(server.local_addrs()[0], rules).expect("failed to start the proxy");
+        let handle = server.handle();
+        let serving = thread::spawn(move || server.run());
+        Harness {
+            root,
+            proxy,
+            handle,
+            serving: Some(serving),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn proxy(&self) -> &Proxy {
+        &self.proxy
+    }
+
+    pub fn client(&self) -> TftpClientBuilder {
+        TftpClient::builder(self.proxy.address())
+            .timeout(TIMEOUT)
+            .retries(RETRIES)
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        self.handle.shutdown(Duration::ZERO);
+        if let Some(serving) = self.serving.take() {
+            let _ = serving.join();
+        }
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+// A writer whose contents stay reachable once the client has taken it.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn contents(&self) -> Vec<u8> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Bytes that differ from block to block, so that a block delivered twice or
+// in the wrong place shows.
+pub fn contents(size: usize) -> Vec<u8> {
+    let mut state: u32 = 0x9e37_79b9;
+    (0..size)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
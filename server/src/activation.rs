@@ -0,0 +1,79 @@
+use crate::daemon;
+use std::env;
+use std::io::{Error, ErrorKind};
+use std::mem;
+use std::net::UdpSocket;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::process;
+
+// First descriptor passed by systemd, see sd_listen_fds(3).
+const LISTEN_FDS_START: RawFd = 3;
+
+// Sockets handed over by systemd socket activation, empty when the server
+// was not started that way.
+pub fn systemd_sockets() -> Result<Vec<UdpSocket>, Error> {
+    let pid = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok());
+    if pid != Some(process::id()) {
+        return Ok(Vec::new());
+    }
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .unwrap_or(0);
+    // keep them from leaking into anything we start
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            check_datagram_socket(fd)?;
+            set_close_on_exec(fd)?;
+            Ok(unsafe { UdpSocket::from_raw_fd(fd) })
+        })
+        .collect()
+}
+
+// In inetd "wait" mode standard input is the bound socket the first request
+// arrived on. Standard output and error are the same socket, so they are
+// pointed at /dev/null before anything gets printed.
+pub fn inetd_socket() -> Result<UdpSocket, Error> {
+    check_datagram_socket(0)?;
+    let fd = unsafe { libc::dup(0) };
+    if fd == -1 {
+        return Err(Error::last_os_error());
+    }
+    set_close_on_exec(fd)?;
+    daemon::detach_stdio()?;
+    Ok(unsafe { UdpSocket::from_raw_fd(fd) })
+}
+
+fn check_datagram_socket(fd: RawFd) -> Result<(), Error> {
+    let mut socket_type: libc::c_int = 0;
+    let mut length = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            &mut socket_type as *mut libc::c_int as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    if result == -1 || socket_type != libc::SOCK_DGRAM {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("file descriptor {} is not a datagram socket", fd),
+        ));
+    }
+    Ok(())
+}
+
+fn set_close_on_exec(fd: RawFd) -> Result<(), Error> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags == -1 || unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,289 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tftp_libs::config::{parse_address, DEFAULT_PORT};
+use tftp_libs::server::ServerMode;
+use tftp_libs::{FileNameEncoding, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE};
+
+pub const USAGE: &str = "Usage: server [OPTIONS]
+
+Options given on the command line take precedence over the configuration file.
+
+Options:
+      --config <FILE>        TOML configuration file to load
+      --check-config         Validate the configuration and exit
+  -a, --address <ADDR>       IPv4 or IPv6 address to listen on, may be repeated
+                             [default: 127.0.0.1]
+  -p, --port <PORT>          Port to listen on when the address has none [default: 69]
+  -s, --root <DIR>           Directory files are served from [default: .]
+  -r, --read-only            Reject every write request
+  -w, --write-only           Reject every read request
+  -c, --create               Allow uploads to create new files
+      --filename-encoding <ENC>
+                             Decoding of requested file names: ascii, utf-8, or
+                             lossy to replace invalid UTF-8 [default: utf-8]
+      --acl <FILE>           Access control list to apply to requests
+  -t, --timeout <SECS>       Seconds to wait before retransmitting [default: 5]
+  -R, --retries <COUNT>      Retransmissions before a transfer is abandoned [default: 5]
+  -B, --max-blksize <BYTES>  Largest block size granted to clients [default: 1468]
+      --shutdown-timeout <SECS>
+                             Seconds transfers may take to finish on shutdown [default: 30]
+  -v, --verbose              Log more details, may be repeated
+      --log-file <FILE>      Append log lines to this file instead of standard
+                             error, which is lost once the server daemonizes
+      --audit-log <FILE>     Append a line for every finished transfer to this file
+      --metrics <ADDR>       Serve Prometheus metrics at http://ADDR/metrics, needs
+                             the server built with the metrics feature
+      --idle-timeout <SECS>  Exit after this many seconds without a request
+      --session-timeout <SECS>
+                             Drop a transfer the client has not advanced for this
+                             long [default: timeout * (retries + 1)]
+  -i, --inetd                Serve the socket inetd passes on standard input in
+                             wait mode, exiting once idle [default idle: 60]
+  -f, --foreground           Stay attached to the terminal instead of daemonizing
+  -u, --user <USER>          User to switch to once the sockets are bound
+  -g, --group <GROUP>        Group to switch to [default: the user's group]
+      --chroot               Confine the server to its root directory, which
+                             also disables reloading on SIGHUP
+      --allow-root           Keep running as root when no user is given
+  -P, --pidfile <FILE>       Write the process id to this file, removed on exit
+                             unless the chroot or the user switched to keeps
+                             the server from it
+  -h, --help                 Print this help
+
+Sockets passed through systemd socket activation (LISTEN_FDS) are served
+in addition to the listen addresses, without daemonizing.
+
+Signals:
+  SIGHUP                     Reload the configuration file and access control list
+  SIGINT, SIGTERM            Refuse new requests and shut down once transfers finish,
+                             a second signal aborts the remaining transfers";
+
+// Settings left as `None` fall back to the configuration file, then to the
+// server defaults.
+pub struct Options {
+    pub config: Option<PathBuf>,
+    pub check_config: bool,
+    pub addresses: Vec<SocketAddr>,
+    pub root: Option<PathBuf>,
+    pub mode: Option<ServerMode>,
+    pub create: Option<bool>,
+    pub file_name_encoding: Option<FileNameEncoding>,
+    pub acl: Option<PathBuf>,
+    pub timeout: Option<Duration>,
+    pub retries: Option<u32>,
+    pub max_block_size: Option<usize>,
+    pub shutdown_timeout: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+    pub session_timeout: Option<Duration>,
+    pub inetd: bool,
+    pub verbosity: u8,
+    pub log_file: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    pub metrics: Option<SocketAddr>,
+    pub foreground: bool,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub chroot: bool,
+    pub allow_root: bool,
+    pub pidfile: Option<PathBuf>,
+}
+
+pub enum Command {
+    Run(Box<Options>),
+    Help,
+}
+
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut config = None;
+    let mut check_config = false;
+    let mut addresses = Vec::new();
+    let mut port = None;
+    let mut root = None;
+    let mut read_only = false;
+    let mut write_only = false;
+    let mut create = None;
+    let mut file_name_encoding = None;
+    let mut acl = None;
+    let mut timeout = None;
+    let mut retries = None;
+    let mut max_block_size = None;
+    let mut shutdown_timeout = None;
+    let mut idle_timeout = None;
+    let mut session_timeout = None;
+    let mut inetd = false;
+    let mut verbosity = 0;
+    let mut log_file = None;
+    let mut audit_log = None;
+    let mut metrics = None;
+    let mut foreground = false;
+    let mut user = None;
+    let mut group = None;
+    let mut chroot = false;
+    let mut allow_root = false;
+    let mut pidfile = None;
+
+    let mut args = expand_flags(args).into_iter();
+    while let Some((flag, inline_value)) = args.next() {
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next().map(|(value, _)| value))
+                .ok_or_else(|| format!("{} requires a value", flag))
+        };
+        match flag.as_str() {
+            "--config" => config = Some(PathBuf::from(value()?)),
+            "--check-config" => check_config = true,
+            "-a" | "--address" => addresses.push(value()?),
+            "-p" | "--port" => port = Some(parse_number(&flag, &value()?)?),
+            "-s" | "--root" => root = Some(PathBuf::from(value()?)),
+            "-r" | "--read-only" => read_only = true,
+            "-w" | "--write-only" => write_only = true,
+            "-c" | "--create" => create = Some(true),
+            "--filename-encoding" => {
+                let encoding = value()?;
+                file_name_encoding = Some(
+                    FileNameEncoding::parse(&encoding)
+                        .ok_or_else(|| format!("unknown filename encoding {}", encoding))?,
+                );
+            }
+            "--acl" => acl = Some(PathBuf::from(value()?)),
+            "-t" | "--timeout" => {
+                let seconds: u64 = parse_number(&flag, &value()?)?;
+                if !(1..=255).contains(&seconds) {
+                    return Err("timeout must be between 1 and 255 seconds".to_string());
+                }
+                timeout = Some(Duration::from_secs(seconds));
+            }
+            "-R" | "--retries" => retries = Some(parse_number(&flag, &value()?)?),
+            "-B" | "--max-blksize" => {
+                let size = parse_number(&flag, &value()?)?;
+                if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&size) {
+                    return Err(format!(
+                        "block size must be between {} and {}",
+                        MIN_BLOCK_SIZE, MAX_BLOCK_SIZE
+                    ));
+                }
+                max_block_size = Some(size);
+            }
+            "--shutdown-timeout" => {
+                shutdown_timeout = Some(Duration::from_secs(parse_number(&flag, &value()?)?));
+            }
+            "-v" | "--verbose" => verbosity += 1,
+            "--log-file" => log_file = Some(PathBuf::from(value()?)),
+            "--audit-log" => audit_log = Some(PathBuf::from(value()?)),
+            "--metrics" => metrics = Some(parse_number(&flag, &value()?)?),
+            "--session-timeout" => {
+                let seconds: u64 = parse_number(&flag, &value()?)?;
+                if seconds == 0 {
+                    return Err("session timeout must be at least 1 second".to_string());
+                }
+                session_timeout = Some(Duration::from_secs(seconds));
+            }
+            "--idle-timeout" => {
+                idle_timeout = Some(Duration::from_secs(parse_number(&flag, &value()?)?));
+            }
+            "-i" | "--inetd" => inetd = true,
+            "-f" | "--foreground" => foreground = true,
+            "-u" | "--user" => user = Some(value()?),
+            "-g" | "--group" => group = Some(value()?),
+            "--chroot" => chroot = true,
+            "--allow-root" => allow_root = true,
+            "-P" | "--pidfile" => pidfile = Some(PathBuf::from(value()?)),
+            "-h" | "--help" => return Ok(Command::Help),
+            _ if flag.starts_with('-') => return Err(format!("unknown option {}", flag)),
+            _ => return Err(format!("unexpected argument {}", flag)),
+        }
+    }
+
+    let mode = match (read_only, write_only) {
+        (true, true) => {
+            return Err("--read-only and --write-only cannot be combined".to_string());
+        }
+        (true, false) => Some(ServerMode::ReadOnly),
+        (false, true) => Some(ServerMode::WriteOnly),
+        (false, false) => None,
+    };
+    if let Some(root) = &root {
+        if !root.is_dir() {
+            return Err(format!("root {} is not a directory", root.display()));
+        }
+    }
+    if check_config && config.is_none() {
+        return Err("--check-config requires --config".to_string());
+    }
+    // a port on its own still listens on the default address
+    if addresses.is_empty() && port.is_some() {
+        addresses.push("127.0.0.1".to_string());
+    }
+    let addresses = addresses
+        .iter()
+        .map(|address| {
+            parse_address(address, port.unwrap_or(DEFAULT_PORT))
+                .ok_or_else(|| format!("invalid listen address {}", address))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Command::Run(Box::new(Options {
+        config,
+        check_config,
+        addresses,
+        root,
+        mode,
+        create,
+        file_name_encoding,
+        acl,
+        timeout,
+        retries,
+        max_block_size,
+        shutdown_timeout,
+        idle_timeout,
+        session_timeout,
+        inetd,
+        verbosity,
+        log_file,
+        audit_log,
+        metrics,
+        foreground,
+        user,
+        group,
+        chroot,
+        allow_root,
+        pidfile,
+    })))
+}
+
+// Splits `--flag=value` and clusters of short flags such as `-vvf` so that
+// every entry is a single flag with an optional inline value.
+fn expand_flags<I: IntoIterator<Item = String>>(args: I) -> Vec<(String, Option<String>)> {
+    let mut expanded = Vec::new();
+    for arg in args {
+        if let Some(long) = arg.strip_prefix("--") {
+            match long.split_once('=') {
+                Some((name, value)) => {
+                    expanded.push((format!("--{}", name), Some(value.to_string())))
+                }
+                None => expanded.push((arg.clone(), None)),
+            }
+        } else if arg.len() > 2 && arg.starts_with('-') {
+            for (index, flag) in arg[1..].char_indices() {
+                if "apsRtBPug".contains(flag) {
+                    let rest = &arg[index + 2..];
+                    let value = (!rest.is_empty()).then(|| rest.to_string());
+                    expanded.push((format!("-{}", flag), value));
+                    break;
+                }
+                expanded.push((format!("-{}", flag), None));
+            }
+        } else {
+            expanded.push((arg, None));
+        }
+    }
+    expanded
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {} for {}", value, flag))
+}
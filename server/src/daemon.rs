@@ -0,0 +1,45 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Error;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::process;
+
+// Detaches from the controlling terminal, the parent process exits straight
+// away so only the daemon keeps running. Standard error stays open for the
+// rest of the start-up to report failures, `detach_stdio` closes it after.
+pub fn daemonize() -> Result<(), Error> {
+    unsafe {
+        match libc::fork() {
+            -1 => return Err(Error::last_os_error()),
+            0 => {}
+            _ => libc::_exit(0),
+        }
+        if libc::setsid() == -1 {
+            return Err(Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// Points standard input, output and error at /dev/null.
+pub fn detach_stdio() -> Result<(), Error> {
+    redirect_stdio(&dev_null()?)
+}
+
+pub fn dev_null() -> Result<File, Error> {
+    OpenOptions::new().read(true).write(true).open("/dev/null")
+}
+
+// Points standard input, output and error at `file`.
+pub fn redirect_stdio(file: &File) -> Result<(), Error> {
+    for fd in 0..3 {
+        if unsafe { libc::dup2(file.as_raw_fd(), fd) } == -1 {
+            return Err(Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+pub fn write_pidfile(path: &Path) -> Result<(), Error> {
+    fs::write(path, format!("{}\n", process::id()))
+}
@@ -1,170 +1,365 @@
-use std::fs::File;
-use std::io::{BufRead, BufWriter, Seek, SeekFrom, Write};
-use std::net::{SocketAddr, UdpSocket};
-use tftp_libs::{
-    extract_message, get_read_file_info, send_error_message, send_tftp_message, Message,
-    SessionRegistry, TftpSessionInfo,
-};
+mod activation;
+mod cli;
+mod daemon;
+mod privileges;
 
-fn main() {
-    let socket = UdpSocket::bind("127.0.0.1:69").expect("Failed to bind to udp socket");
-    println!("Started TFTP sever ...");
+use cli::{Command, Options};
+use log::{error, info, warn, LevelFilter};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+use tftp_libs::acl::Acl;
+use tftp_libs::config::Config;
+use tftp_libs::logging;
+use tftp_libs::server::{ServerConfig, ServerHandle, TftpServer, TftpServerBuilder};
 
-    let mut session_registry = SessionRegistry::new();
-    let mut buf = [0; 520];
-    loop {
-        let receive_result = socket.recv_from(&mut buf);
-        if receive_result.is_err() {
-            println!("Failed to receive data");
-            continue;
+fn main() {
+    let options = match cli::parse(env::args().skip(1)) {
+        Ok(Command::Run(options)) => *options,
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return;
         }
-        let (amt, src) = receive_result.unwrap();
-        let received_buffer = &mut buf[..amt];
-        session_registry.register(src, TftpSessionInfo::new()); //try to register the session incase its new.
-        handle_request(&socket, src, received_buffer, &mut session_registry);
-    }
-}
+        Err(error) => {
+            eprintln!("error: {}\n\n{}", error, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    let _ = logging::init(log_level(options.verbosity));
 
-fn handle_request(
-    udp_socket: &UdpSocket,
-    source_address: SocketAddr,
-    buffer: &[u8],
-    session_registry: &mut SessionRegistry,
-) {
-    let message = extract_message(buffer);
-    let session_info = session_registry
-        .get_session(source_address)
-        .expect("Unable to get session information");
-    match message {
-        Message::ReadRequest { file_name, mode } => {
-            println!("received request to read {} with mode {}", file_name, mode);
-            // Try to find the file
-            let file_result = get_read_file_info(file_name.clone());
-            let (reader, file_length) = match file_result {
-                Ok((reader, length)) => (reader, length),
-                Err(error) => {
-                    send_error_message(error, udp_socket, &source_address.to_string());
-                    return; // nothing else to do here
-                }
-            };
+    // systemd or inetd already manage the process and own the sockets, taken
+    // first since inetd also hands over standard error
+    let sockets = if options.check_config {
+        Ok(Vec::new())
+    } else if options.inetd {
+        activation::inetd_socket().map(|socket| vec![socket])
+    } else {
+        activation::systemd_sockets()
+    };
+    let sockets = sockets.unwrap_or_else(|error| {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    });
+    let activated = !sockets.is_empty();
 
-            // update the session information
-            session_info.file_name = String::from(file_name);
-            session_info.reader = Some(reader);
-            session_info.block_count = ((file_length / 512) + 1u64) as usize;
+    let config = load_config(&options).unwrap_or_else(|error| {
+        eprintln!("error: {}", error);
+        process::exit(2);
+    });
+    let metrics_address = options.metrics.or(config.metrics_address);
+    if metrics_address.is_some() && !cfg!(feature = "metrics") {
+        eprintln!(
+            "error: metrics are unavailable, the server was built without the metrics feature"
+        );
+        process::exit(2);
+    }
+    if options.check_config {
+        println!("Configuration OK");
+        return;
+    }
+    // opened while still privileged and outside any chroot
+    let log_file = options.log_file.clone().or_else(|| config.log_file.clone());
+    if let Some(log_file) = &log_file {
+        if let Err(error) = logging::log_to_file(log_file) {
+            eprintln!("error: {}", error);
+            process::exit(1);
+        }
+    }
 
-            let contents = session_info
-                .reader
-                .as_mut()
-                .expect("failed to get reader")
-                .fill_buf()
-                .expect("Unable to read file contents");
-            let block_number = 1;
+    let privileges = Privileges {
+        user: options.user.clone().or_else(|| config.user.clone()),
+        group: options.group.clone().or_else(|| config.group.clone()),
+        chroot: options.chroot || config.chroot == Some(true),
+        allow_root: options.allow_root || config.allow_root == Some(true),
+    };
+    if privileges.user.is_none() && privileges::is_root() && !privileges.allow_root {
+        eprintln!("error: refusing to run as root, give --user or --allow-root");
+        process::exit(1);
+    }
 
-            // Send back the first chunk
-            send_tftp_message(
-                udp_socket,
-                Message::Data {
-                    block_number,
-                    data: contents[0..contents.len()].as_ref(),
-                    length: contents.len(),
-                },
-                &source_address.to_string(),
-            );
+    let mut builder = configure(&options, config);
+    for socket in sockets {
+        builder = builder.socket(socket);
+    }
+    #[cfg(feature = "metrics")]
+    if let Some(address) = metrics_address {
+        builder = builder.metrics_address(&address.to_string());
+    }
+    let mut server = builder.build().unwrap_or_else(|error| {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    });
 
-            println!("Sent back first block of {} bytes", contents.len());
+    log::set_max_level(log_level(server.config().verbosity));
+    info!(
+        mode:? = server.config().mode,
+        addresses:? = server.local_addrs();
+        "started TFTP server"
+    );
+    #[cfg(feature = "metrics")]
+    if let Some(address) = server.metrics_addr() {
+        info!(address:% = address; "serving metrics");
+    }
+    let daemonize = !options.foreground && !activated;
+    if daemonize {
+        if log_file.is_none() {
+            eprintln!("warning: logging to standard error, which is discarded once the server daemonizes; give --log-file or --foreground");
         }
-        Message::WriteRequest { file_name, mode } => {
-            println!("received request to write {} with mode {}", file_name, mode);
-            let block_number = 0;
-            session_info.file_name = file_name;
-            send_tftp_message(
-                udp_socket,
-                Message::Ack { block_number },
-                &source_address.to_string(),
-            );
-            println!("Sent ack to start upload");
+        if let Err(error) = daemon::daemonize() {
+            eprintln!("error: unable to daemonize: {}", error);
+            process::exit(1);
         }
-        Message::Data {
-            block_number,
-            data,
-            length,
-        } => {
-            println!(
-                "received data of length {} for block {}",
-                length, block_number
-            );
-            if block_number == 1 {
-                let file =
-                    File::create(session_info.file_name.clone()).expect("Error creating file");
-                let writer = BufWriter::new(file);
-                session_info.writer = Some(writer);
-            }
-            //write the contents to file
-            session_info
-                .writer
-                .as_mut()
-                .expect("Writer not set")
-                .write(data)
-                .expect("Error writing chunk to file");
-            // TODO handle write error and re-request the block??
-            send_tftp_message(
-                udp_socket,
-                Message::Ack { block_number },
-                &source_address.to_string(),
-            );
-            println!("sent back ack for block number {}", block_number);
-            if length < 512 {
-                println!("Upload Complete");
-                session_registry.deregister(source_address);
-            }
+    }
+    // from here on failures are logged, which still reaches the terminal
+    // until standard error is detached
+    let pidfile = options.pidfile.as_ref().map(|pidfile| {
+        daemon::write_pidfile(pidfile)
+            .and_then(|_| pidfile.canonicalize())
+            .unwrap_or_else(|error| {
+                error!(path:% = pidfile.display(), error:% = error; "unable to write the pidfile");
+                process::exit(1);
+            })
+    });
+    let exit = |chroot: Option<&Path>| -> ! {
+        if let Some(pidfile) = &pidfile {
+            remove_pidfile(pidfile, chroot);
+        }
+        process::exit(1);
+    };
+    // opened before a chroot hides it
+    let dev_null = match daemonize.then(daemon::dev_null).transpose() {
+        Ok(dev_null) => dev_null,
+        Err(error) => {
+            error!(error:% = error; "unable to open /dev/null");
+            exit(None);
+        }
+    };
+    let chroot = confine(&privileges, &server.handle()).unwrap_or_else(|error| {
+        error!(error:% = error; "unable to drop privileges");
+        exit(None);
+    });
+    if let Err(error) = handle_signals(options, privileges.chroot, server.handle()) {
+        error!(error:% = error; "unable to install signal handlers");
+        exit(chroot.as_deref());
+    }
+    if let Some(dev_null) = dev_null {
+        if let Err(error) = daemon::redirect_stdio(&dev_null) {
+            error!(error:% = error; "unable to detach from the terminal");
+            exit(chroot.as_deref());
         }
-        Message::Ack { block_number } => {
-            println!("received ack of block {}", block_number);
-            // check if we are done
-            if block_number == session_info.block_count as u16 {
-                println!(
-                    "Received last ack for file name: {}",
-                    session_info.file_name
-                );
-                session_registry.deregister(source_address);
+    }
+    server.run();
+    if let Some(pidfile) = pidfile {
+        remove_pidfile(&pidfile, chroot.as_deref());
+    }
+    info!("server stopped");
+}
+
+// Removes the pidfile on the way out, if the confined server can: a chroot
+// may hide it and the user switched to may not be allowed to delete it, in
+// which case it stays behind.
+fn remove_pidfile(pidfile: &Path, chroot: Option<&Path>) {
+    let confined = match chroot {
+        Some(root) => match pidfile.strip_prefix(root) {
+            Ok(relative) => Path::new("/").join(relative),
+            Err(_) => {
+                warn!(path:% = pidfile.display(); "pidfile outside the chroot left behind");
                 return;
             }
+        },
+        None => pidfile.to_path_buf(),
+    };
+    if let Err(error) = fs::remove_file(&confined) {
+        warn!(path:% = pidfile.display(), error:% = error; "pidfile left behind");
+    }
+}
 
-            println!("Reading next block of file: {}", session_info.file_name);
-            let reader = session_info.reader.as_mut().expect("Reader not found");
-            reader
-                .seek(SeekFrom::Current(512))
-                .expect("Unable to seek file"); // move to next block
-            let contents = reader.fill_buf().expect("Unable to read file contents");
-            let block_number = block_number + 1;
+// What to give up once every socket is bound.
+struct Privileges {
+    user: Option<String>,
+    group: Option<String>,
+    chroot: bool,
+    allow_root: bool,
+}
 
-            //TODO send back error if block is out of range
-            send_tftp_message(
-                udp_socket,
-                Message::Data {
-                    block_number,
-                    data: contents[0..contents.len()].as_ref(),
-                    length: contents.len(),
-                },
-                &source_address.to_string(),
-            );
+// Optionally confines the server to its root directory, then switches to the
+// configured user and group. Staying root needs an explicit override. Returns
+// the directory the server is chrooted into.
+fn confine(privileges: &Privileges, handle: &ServerHandle) -> Result<Option<PathBuf>, String> {
+    let user = match &privileges.user {
+        Some(user) => Some(privileges::resolve_user(user).map_err(|error| error.to_string())?),
+        None => None,
+    };
+    let gid = match &privileges.group {
+        Some(group) => Some(privileges::resolve_group(group).map_err(|error| error.to_string())?),
+        None => user.map(|(_, gid)| gid),
+    };
+    let mut chroot = None;
+    if privileges.chroot {
+        let mut config = ServerConfig::clone(&handle.config());
+        let root = config
+            .root
+            .canonicalize()
+            .map_err(|error| format!("{}: {}", config.root.display(), error))?;
+        // listener roots have to live below the new root
+        for listener_root in config.listener_roots.values_mut() {
+            let canonical = listener_root
+                .canonicalize()
+                .map_err(|error| format!("{}: {}", listener_root.display(), error))?;
+            let relative = canonical.strip_prefix(&root).map_err(|_| {
+                format!(
+                    "{} is outside the chroot {}",
+                    listener_root.display(),
+                    root.display()
+                )
+            })?;
+            *listener_root = Path::new("/").join(relative);
+        }
+        privileges::chroot(&root)
+            .map_err(|error| format!("unable to chroot into {}: {}", root.display(), error))?;
+        config.root = PathBuf::from("/");
+        handle.reload(config);
+        chroot = Some(root);
+    }
+    privileges::drop_privileges(user.map(|(uid, _)| uid), gid)
+        .map_err(|error| format!("unable to switch user: {}", error))?;
+    if privileges::is_root() && !privileges.allow_root {
+        return Err("refusing to run as root, give --user or --allow-root".to_string());
+    }
+    Ok(chroot)
+}
 
-            println!(
-                "Sent back block number {} of {} bytes",
-                block_number,
-                contents.len()
-            );
-            if contents.len() < 512 {
-                println!("Sent back last block to client");
-            }
+const INETD_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn load_config(options: &Options) -> Result<Config, String> {
+    let mut config = match &options.config {
+        Some(file_name) => {
+            Config::load(file_name).map_err(|error| format!("invalid configuration {}", error))?
         }
-        Message::Error {
-            error_code,
-            error_message,
-        } => {
-            eprintln!("received error message :{}", error_message);
-            eprintln!("received error code :{}", error_code);
+        None => Config::default(),
+    };
+    if let Some(file_name) = &options.acl {
+        let acl = Acl::load(file_name).map_err(|error| {
+            format!(
+                "invalid access control list {}: {}",
+                file_name.display(),
+                error
+            )
+        })?;
+        config.acl = Some(acl);
+    }
+    Ok(config)
+}
+
+// SIGHUP re-reads the configuration file and access control list, keeping the
+// running configuration if they are broken, and reopens the log file. SIGINT and SIGTERM start a
+// graceful shutdown and a second one cuts it short.
+fn handle_signals(options: Options, chrooted: bool, handle: ServerHandle) -> std::io::Result<()> {
+    let mut signals = Signals::new([SIGHUP, SIGINT, SIGTERM])?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal != SIGHUP {
+                if handle.shutdown_deadline().is_some() {
+                    info!("aborting the remaining transfers");
+                    handle.shutdown(Duration::ZERO);
+                } else {
+                    let grace = handle.config().shutdown_timeout;
+                    info!(grace_s = grace.as_secs(); "shutting down");
+                    handle.shutdown(grace);
+                }
+                continue;
+            }
+            if chrooted {
+                error!("the configuration cannot be reloaded inside a chroot");
+                continue;
+            }
+            let reloaded = load_config(&options).and_then(|config| {
+                let log_file = options.log_file.clone().or_else(|| config.log_file.clone());
+                configure(&options, config)
+                    .build_config()
+                    .map(|config| (config, log_file))
+                    .map_err(|error| error.to_string())
+            });
+            match reloaded {
+                Ok((config, log_file)) => {
+                    // picks up a rotated log file
+                    if let Some(log_file) = log_file {
+                        if let Err(error) = logging::log_to_file(log_file) {
+                            error!(error:% = error; "keeping the current log file");
+                        }
+                    }
+                    log::set_max_level(log_level(config.verbosity));
+                    handle.reload(config);
+                    info!("reloaded configuration");
+                }
+                Err(error) => error!(error:% = error; "keeping the current configuration"),
+            }
         }
+    });
+    Ok(())
+}
+
+// Transfers are logged by default, -v adds the protocol details and -vv every
+// packet.
+fn log_level(verbosity: u8) -> LevelFilter {
+    match verbosity {
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+// Layers the command-line options over the configuration file.
+fn configure(options: &Options, mut config: Config) -> TftpServerBuilder {
+    if !options.addresses.is_empty() {
+        config.listeners.clear();
+    }
+    let inetd_idle_timeout =
+        (options.inetd && config.idle_timeout.is_none()).then_some(INETD_IDLE_TIMEOUT);
+    let mut builder = TftpServer::builder().config(&config);
+    if let Some(mode) = options.mode {
+        builder = builder.mode(mode);
+    }
+    if let Some(root) = &options.root {
+        builder = builder.root(root);
+    }
+    if let Some(create) = options.create {
+        builder = builder.create(create);
+    }
+    if let Some(file_name_encoding) = options.file_name_encoding {
+        builder = builder.file_name_encoding(file_name_encoding);
+    }
+    if let Some(timeout) = options.timeout {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(retries) = options.retries {
+        builder = builder.retries(retries);
+    }
+    if let Some(max_block_size) = options.max_block_size {
+        builder = builder.max_block_size(max_block_size);
+    }
+    if let Some(shutdown_timeout) = options.shutdown_timeout {
+        builder = builder.shutdown_timeout(shutdown_timeout);
+    }
+    if let Some(idle_timeout) = options.idle_timeout.or(inetd_idle_timeout) {
+        builder = builder.idle_timeout(idle_timeout);
+    }
+    if let Some(session_timeout) = options.session_timeout {
+        builder = builder.session_timeout(session_timeout);
+    }
+    if options.verbosity > 0 {
+        builder = builder.verbosity(options.verbosity);
+    }
+    if let Some(audit_log) = &options.audit_log {
+        builder = builder.audit_log(audit_log);
+    }
+    for address in &options.addresses {
+        builder = builder.address(&address.to_string());
     }
+    builder
 }
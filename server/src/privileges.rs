@@ -0,0 +1,76 @@
+use std::ffi::{CString, OsStr};
+use std::io::{Error, ErrorKind};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+// Looks up a user by name or numeric id, returning it with its primary group.
+pub fn resolve_user(user: &str) -> Result<(libc::uid_t, libc::gid_t), Error> {
+    let entry = match user.parse::<libc::uid_t>() {
+        Ok(uid) => unsafe { libc::getpwuid(uid) },
+        Err(_) => {
+            let name = c_string(user)?;
+            unsafe { libc::getpwnam(name.as_ptr()) }
+        }
+    };
+    if entry.is_null() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("unknown user {}", user),
+        ));
+    }
+    let entry = unsafe { &*entry };
+    Ok((entry.pw_uid, entry.pw_gid))
+}
+
+pub fn resolve_group(group: &str) -> Result<libc::gid_t, Error> {
+    if let Ok(gid) = group.parse::<libc::gid_t>() {
+        return Ok(gid);
+    }
+    let name = c_string(group)?;
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if entry.is_null() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("unknown group {}", group),
+        ));
+    }
+    Ok(unsafe { (*entry).gr_gid })
+}
+
+// Makes `directory` the root of the file system for this process.
+pub fn chroot(directory: &Path) -> Result<(), Error> {
+    let path = c_string(directory.as_os_str())?;
+    unsafe {
+        if libc::chroot(path.as_ptr()) == -1 || libc::chdir(c"/".as_ptr()) == -1 {
+            return Err(Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// Switches to the given group and user for good, the group first while
+// there is still the right to do so.
+pub fn drop_privileges(uid: Option<libc::uid_t>, gid: Option<libc::gid_t>) -> Result<(), Error> {
+    unsafe {
+        if let Some(gid) = gid {
+            if libc::setgroups(1, &gid) == -1 || libc::setgid(gid) == -1 {
+                return Err(Error::last_os_error());
+            }
+        }
+        if let Some(uid) = uid {
+            if libc::setuid(uid) == -1 {
+                return Err(Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn c_string<S: AsRef<OsStr>>(value: S) -> Result<CString, Error> {
+    CString::new(value.as_ref().as_bytes())
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "name contains a NUL byte"))
+}
@@ -0,0 +1,76 @@
+// Runs the server binary with --check-config, which validates a configuration
+// file and exits without binding anything.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+// A configuration file written for one test, removed afterwards.
+struct ConfigFile(PathBuf);
+
+impl ConfigFile {
+    fn new(name: &str, contents: &str) -> ConfigFile {
+        let path = std::env::temp_dir().join(format!(
+            "tftp_check_config_{}_{}.toml",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, contents).unwrap();
+        ConfigFile(path)
+    }
+
+    fn check(&self) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_rust_tftp_server"))
+            .arg("--config")
+            .arg(&self.0)
+            .arg("--check-config")
+            .output()
+            .unwrap()
+    }
+}
+
+impl Drop for ConfigFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn valid_configuration_is_ok() {
+    let config = ConfigFile::new(
+        "valid",
+        "mode = \"read-only\"\n\n[[listener]]\naddress = \"127.0.0.1:6969\"\n",
+    );
+    let output = config.check();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Configuration OK\n"
+    );
+}
+
+#[test]
+fn invalid_configuration_names_the_file_and_line() {
+    let config = ConfigFile::new("invalid", "create = true\n\nmode = \"bogus\"\n");
+    let output = config.check();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "{}: line 3: unknown mode bogus",
+            config.0.display()
+        )),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn missing_configuration_is_reported() {
+    let config = ConfigFile::new("missing", "");
+    fs::remove_file(&config.0).unwrap();
+    let output = config.check();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(&config.0.display().to_string()));
+}